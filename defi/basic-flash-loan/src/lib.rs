@@ -1,26 +1,83 @@
 use scrypto::prelude::*;
 
+external_component! {
+    PriceOracleComponentTarget {
+        fn get_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Option<Decimal>;
+    }
+}
+
+/// Returns the fee charged on loans of an asset until the owner sets another one.
+fn default_fee() -> Decimal {
+    dec!("0.001")
+}
+
+/// Returns the premium charged on repayments in an alternative asset until the owner sets another one.
+fn default_alternative_premium() -> Decimal {
+    dec!("0.01")
+}
+
 #[derive(NonFungibleData, ScryptoSbor)]
 pub struct LoanDue {
+    pub resource_address: ResourceAddress,
+    pub loan_amount: Decimal,
     pub amount_due: Decimal,
+    pub protocol_fee: Decimal,
+}
+
+#[derive(ScryptoSbor)]
+pub struct LoanPool {
+    /// The liquidity available for loans of this asset
+    pub loan_vault: Vault,
+    /// The amount lent out of this pool and not yet repaid
+    pub outstanding_amount: Decimal,
+    /// The LP token representing shares of this pool
+    pub lp_resource_address: ResourceAddress,
+    /// The fee charged on loans of this asset
+    pub fee: Decimal,
+    /// The protocol's cut of the fees, which the owner may withdraw
+    pub protocol_fees: Vault,
+    /// The other assets loans of this asset may be repaid in
+    pub allowed_alternatives: Vec<ResourceAddress>,
+    /// The repayments received in other assets, which belong to the liquidity providers
+    pub alternative_vaults: HashMap<ResourceAddress, Vault>,
+    /// The value in this asset of the repayments received in other assets, at the price they were accepted at
+    pub alternative_value: Decimal,
+    /// The protocol's cut of the fees on repayments in other assets, which the owner may withdraw
+    pub alternative_protocol_fees: HashMap<ResourceAddress, Vault>,
 }
 
 #[blueprint]
 mod basic_flash_loan {
     struct BasicFlashLoan {
-        loan_vault: Vault,
+        pools: HashMap<ResourceAddress, LoanPool>,
         auth_vault: Vault,
         transient_resource_address: ResourceAddress,
+        /// The number of loans taken and not yet repaid in this transaction
+        outstanding_loans: u32,
+        /// The owner badge resource address
+        owner_badge: ResourceAddress,
+        /// Whether lending is paused
+        paused: bool,
+        /// The largest share of an asset's liquidity the loans outstanding at once may take
+        max_loan_share: Decimal,
+        /// The share of every loan fee which goes to the protocol instead of the liquidity providers
+        protocol_fee_share: Decimal,
+        /// The price oracle used to value repayments in other assets
+        oracle_address: Option<ComponentAddress>,
+        /// The premium over the amount due charged on repayments in other assets
+        alternative_premium: Decimal,
     }
 
     impl BasicFlashLoan {
         /// The most elementary possible flash loan.  Creates a loan pool from whatever is initially supplied,
-        /// provides loans with a .1% fee, and lets anyone freely add liquidity.
+        /// provides loans with a .1% fee, and lets anyone freely add liquidity of any asset.
         ///
-        /// Does NOT reward liquidity providers in any way or provide a way to remove liquidity from the pool.
-        /// Minting LP tokens for rewards, and removing liquidity, is covered in other examples, such as:
-        /// https://github.com/radixdlt/scrypto-examples/tree/main/defi/radiswap
-        pub fn instantiate_default(initial_liquidity: Bucket) -> ComponentAddress {
+        /// Liquidity providers receive LP tokens for each asset they supply, which can be redeemed for their share
+        /// of that asset's pool, including the fees it has earned.  The owner badge returned allows changing the
+        /// fees, pausing lending, capping loans and withdrawing the protocol's cut of the fees.
+        pub fn instantiate_default(
+            initial_liquidity: Bucket,
+        ) -> (ComponentAddress, Bucket, Bucket) {
             let auth_token = ResourceBuilder::new_fungible()
                 .divisibility(DIVISIBILITY_NONE)
                 .metadata("name", "Admin authority for BasicFlashLoan")
@@ -37,31 +94,203 @@ mod basic_flash_loan {
                 .restrict_deposit(rule!(deny_all), LOCKED)
                 .create_with_no_initial_supply();
 
-            Self {
-                loan_vault: Vault::with_bucket(initial_liquidity),
+            let owner_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "BasicFlashLoan Owner Badge")
+                .mint_initial_supply(1);
+
+            let rules = AccessRulesConfig::new()
+                .method(
+                    "set_fee",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_paused",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_max_loan_share",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_protocol_fee_share",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "withdraw_protocol_fees",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "withdraw_alternative_protocol_fees",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_oracle",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_alternative_premium",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "allow_alternative_repayment",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "disallow_alternative_repayment",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .default(rule!(allow_all), AccessRule::DenyAll);
+
+            let mut component = Self {
+                pools: HashMap::new(),
                 auth_vault: Vault::with_bucket(auth_token),
                 transient_resource_address: address,
+                outstanding_loans: 0,
+                owner_badge: owner_badge.resource_address(),
+                paused: false,
+                max_loan_share: dec!("1"),
+                protocol_fee_share: Decimal::zero(),
+                oracle_address: None,
+                alternative_premium: default_alternative_premium(),
+            };
+            let lp_tokens = component.add_liquidity(initial_liquidity);
+            let component_address = component.instantiate().globalize_with_access_rules(rules);
+
+            (component_address, lp_tokens, owner_badge)
+        }
+
+        pub fn available_liquidity(&self, resource_address: ResourceAddress) -> Decimal {
+            match self.pools.get(&resource_address) {
+                Some(pool) => pool.loan_vault.amount(),
+                None => Decimal::zero(),
             }
-            .instantiate()
-            .globalize()
         }
 
-        pub fn available_liquidity(&self) -> Decimal {
-            self.loan_vault.amount()
+        /// Returns the assets which can be borrowed.
+        pub fn get_assets(&self) -> Vec<ResourceAddress> {
+            self.pools.keys().cloned().collect()
         }
 
-        pub fn add_liquidity(&mut self, tokens: Bucket) {
-            self.loan_vault.put(tokens);
+        /// Returns the resource address of the LP token for an asset's pool.
+        pub fn lp_resource_address(&self, resource_address: ResourceAddress) -> ResourceAddress {
+            self.get_pool(resource_address).lp_resource_address
         }
 
-        pub fn take_loan(&mut self, loan_amount: Decimal) -> (Bucket, Bucket) {
+        /// Adds liquidity to the pool of the tokens' asset, creating the pool if it is the first time the asset
+        /// is supplied, and returns the LP tokens representing the share of the pool.
+        pub fn add_liquidity(&mut self, tokens: Bucket) -> Bucket {
             assert!(
-                loan_amount <= self.loan_vault.amount(),
+                self.outstanding_loans == 0,
+                "Liquidity can't change while a loan is outstanding"
+            );
+            assert!(!tokens.is_empty(), "You must pass in some tokens");
+
+            let resource_address = tokens.resource_address();
+            if !self.pools.contains_key(&resource_address) {
+                let lp_resource_address = ResourceBuilder::new_fungible()
+                    .divisibility(DIVISIBILITY_MAXIMUM)
+                    .metadata("name", "LP token for BasicFlashLoan")
+                    .mintable(rule!(require(self.auth_vault.resource_address())), LOCKED)
+                    .burnable(rule!(require(self.auth_vault.resource_address())), LOCKED)
+                    .create_with_no_initial_supply();
+                self.pools.insert(
+                    resource_address,
+                    LoanPool {
+                        loan_vault: Vault::new(resource_address),
+                        outstanding_amount: Decimal::zero(),
+                        lp_resource_address,
+                        fee: default_fee(),
+                        protocol_fees: Vault::new(resource_address),
+                        allowed_alternatives: Vec::new(),
+                        alternative_vaults: HashMap::new(),
+                        alternative_value: Decimal::zero(),
+                        alternative_protocol_fees: HashMap::new(),
+                    },
+                );
+            }
+
+            // Mint LP tokens according to the share of the pool the provider is contributing
+            let pool_value = self.get_pool_value(resource_address);
+            let pool = self.pools.get_mut(&resource_address).unwrap();
+            let lp_resource_manager = borrow_resource_manager!(pool.lp_resource_address);
+            let supply_to_mint = if lp_resource_manager.total_supply().is_zero() {
+                tokens.amount()
+            } else {
+                lp_resource_manager.total_supply() * tokens.amount() / pool_value
+            };
+            pool.loan_vault.put(tokens);
+
+            self.auth_vault
+                .authorize(|| lp_resource_manager.mint(supply_to_mint))
+        }
+
+        /// Redeems LP tokens for their share of the pool, including the fees it has earned.  Returns the share of
+        /// the pooled asset first, followed by the share of every repayment received in another asset.
+        pub fn remove_liquidity(&mut self, lp_tokens: Bucket) -> Vec<Bucket> {
+            assert!(
+                self.outstanding_loans == 0,
+                "Liquidity can't change while a loan is outstanding"
+            );
+
+            let pool = self
+                .pools
+                .values_mut()
+                .find(|pool| pool.lp_resource_address == lp_tokens.resource_address())
+                .expect("Wrong token type passed in");
+
+            // Withdraw the share of the pool
+            let share = lp_tokens.amount()
+                / borrow_resource_manager!(pool.lp_resource_address).total_supply();
+            let mut withdrawn = vec![pool.loan_vault.take(pool.loan_vault.amount() * share)];
+            for vault in pool.alternative_vaults.values_mut() {
+                withdrawn.push(vault.take(vault.amount() * share));
+            }
+            pool.alternative_value -= pool.alternative_value * share;
+
+            // Burn the LP tokens received
+            self.auth_vault.authorize(|| lp_tokens.burn());
+
+            withdrawn
+        }
+
+        pub fn take_loan(
+            &mut self,
+            resource_address: ResourceAddress,
+            loan_amount: Decimal,
+        ) -> (Bucket, Bucket) {
+            assert!(!self.paused, "Lending is paused");
+            assert!(
+                loan_amount > Decimal::zero(),
+                "Loan amount must be positive"
+            );
+            assert!(
+                loan_amount <= self.available_liquidity(resource_address),
                 "Not enough liquidity to supply this loan!"
             );
 
-            // Calculate how much we must be repaid
-            let amount_due = loan_amount * dec!("1.001");
+            // Count every loan outstanding, so that the cap can't be dodged by splitting a loan up
+            let outstanding_amount = self.get_pool(resource_address).outstanding_amount;
+            let total_liquidity = self.available_liquidity(resource_address) + outstanding_amount;
+            assert!(
+                outstanding_amount + loan_amount <= total_liquidity * self.max_loan_share,
+                "Loans exceed the maximum share of liquidity"
+            );
+
+            // Calculate how much we must be repaid, and the protocol's cut of the fee
+            let fee_amount = loan_amount * self.get_pool(resource_address).fee;
+            let amount_due = loan_amount + fee_amount;
+            let protocol_fee = fee_amount * self.protocol_fee_share;
 
             // Mint an NFT with the loan terms.  Remember that this resource previously had rules defined which
             // forbid it from ever being deposited in any vault.  Thus, once it is present in the transaction
@@ -73,32 +302,269 @@ mod basic_flash_loan {
             let loan_terms = self.auth_vault.authorize(|| {
                 borrow_resource_manager!(self.transient_resource_address).mint_uuid_non_fungible(
                     LoanDue {
+                        resource_address,
+                        loan_amount,
                         amount_due: amount_due,
+                        protocol_fee,
                     },
                 )
             });
-            (self.loan_vault.take(loan_amount), loan_terms)
+            self.outstanding_loans += 1;
+
+            let pool = self.pools.get_mut(&resource_address).unwrap();
+            pool.outstanding_amount += loan_amount;
+            (pool.loan_vault.take(loan_amount), loan_terms)
         }
 
-        pub fn repay_loan(&mut self, loan_repayment: Bucket, loan_terms: Bucket) {
+        pub fn repay_loan(&mut self, mut loan_repayment: Bucket, loan_terms: Bucket) {
             assert!(
                 loan_terms.resource_address() == self.transient_resource_address,
                 "Incorrect resource passed in for loan terms"
             );
 
-            // Verify we are being sent at least the amount due
             let terms: LoanDue = loan_terms.non_fungible().data();
-            assert!(
-                loan_repayment.amount() >= terms.amount_due,
-                "Insufficient repayment given for your loan!"
-            );
+            if loan_repayment.resource_address() == terms.resource_address {
+                // Verify we are being sent at least the amount due, of the resource we lent
+                assert!(
+                    loan_repayment.amount() >= terms.amount_due,
+                    "Insufficient repayment given for your loan!"
+                );
 
-            // We could also verify that the resource being repaid is of the correct kind, and give a friendly
-            // error message if not. For this example we'll just let the engine handle that when we try to deposit
-            self.loan_vault.put(loan_repayment);
+                // Set the protocol's cut aside; the rest of the fee stays in the pool, growing the share of every
+                // liquidity provider
+                let pool = self.pools.get_mut(&terms.resource_address).unwrap();
+                pool.protocol_fees
+                    .put(loan_repayment.take(terms.protocol_fee));
+                pool.loan_vault.put(loan_repayment);
+            } else {
+                // Verify we are being sent at least the value of the amount due plus the premium, in an asset we
+                // accept instead
+                let alternative = loan_repayment.resource_address();
+                assert!(
+                    self.get_pool(terms.resource_address)
+                        .allowed_alternatives
+                        .contains(&alternative),
+                    "Loan can't be repaid in resource {:?}",
+                    alternative
+                );
+                let price = self.get_oracle_price(alternative, terms.resource_address);
+                let amount_due = terms.amount_due * (dec!("1") + self.alternative_premium) / price;
+                assert!(
+                    loan_repayment.amount() >= amount_due,
+                    "Insufficient repayment given for your loan!"
+                );
+
+                // Set the protocol's cut aside at the same price, as for repayments in the asset lent; the rest
+                // goes to the liquidity providers, and counts towards the pool's value at what it settled
+                let pool = self.pools.get_mut(&terms.resource_address).unwrap();
+                pool.alternative_protocol_fees
+                    .get_mut(&alternative)
+                    .unwrap()
+                    .put(loan_repayment.take(terms.protocol_fee / price));
+                pool.alternative_vaults
+                    .get_mut(&alternative)
+                    .unwrap()
+                    .put(loan_repayment);
+                pool.alternative_value += terms.amount_due - terms.protocol_fee;
+            }
 
             // We have our payment; we can now burn the transient token
+            self.pools
+                .get_mut(&terms.resource_address)
+                .unwrap()
+                .outstanding_amount -= terms.loan_amount;
             self.auth_vault.authorize(|| loan_terms.burn());
+            self.outstanding_loans -= 1;
+        }
+
+        /// Sets the fee charged on loans of an asset.
+        pub fn set_fee(&mut self, resource_address: ResourceAddress, fee: Decimal) {
+            assert!(fee >= dec!("0") && fee <= dec!("1"), "Invalid fee");
+            self.get_pool_mut(resource_address).fee = fee;
+        }
+
+        /// Pauses or resumes lending. Liquidity can still be added and removed while lending is paused.
+        pub fn set_paused(&mut self, paused: bool) {
+            self.paused = paused;
+        }
+
+        /// Sets the largest share of an asset's liquidity the loans outstanding at once may take, from 0 to 1.
+        pub fn set_max_loan_share(&mut self, max_loan_share: Decimal) {
+            assert!(
+                max_loan_share >= dec!("0") && max_loan_share <= dec!("1"),
+                "Invalid max loan share"
+            );
+            self.max_loan_share = max_loan_share;
+        }
+
+        /// Sets the share of every loan fee which goes to the protocol, from 0 (off) to 1.
+        pub fn set_protocol_fee_share(&mut self, protocol_fee_share: Decimal) {
+            assert!(
+                protocol_fee_share >= dec!("0") && protocol_fee_share <= dec!("1"),
+                "Invalid protocol fee share"
+            );
+            self.protocol_fee_share = protocol_fee_share;
+        }
+
+        /// Withdraws all protocol fees accrued in an asset.
+        pub fn withdraw_protocol_fees(&mut self, resource_address: ResourceAddress) -> Bucket {
+            self.get_pool_mut(resource_address).protocol_fees.take_all()
+        }
+
+        /// Withdraws all protocol fees accrued on loans of an asset repaid in another asset.
+        pub fn withdraw_alternative_protocol_fees(
+            &mut self,
+            resource_address: ResourceAddress,
+            alternative: ResourceAddress,
+        ) -> Bucket {
+            match self
+                .get_pool_mut(resource_address)
+                .alternative_protocol_fees
+                .get_mut(&alternative)
+            {
+                Some(vault) => vault.take_all(),
+                None => panic!("Loans can't be repaid in resource {:?}", alternative),
+            }
+        }
+
+        /// Sets the price oracle used to value repayments in other assets.
+        pub fn set_oracle(&mut self, oracle_address: ComponentAddress) {
+            self.oracle_address = Some(oracle_address);
+        }
+
+        /// Sets the premium over the amount due charged on repayments in other assets.
+        pub fn set_alternative_premium(&mut self, alternative_premium: Decimal) {
+            assert!(
+                alternative_premium >= dec!("0"),
+                "Invalid alternative premium"
+            );
+            self.alternative_premium = alternative_premium;
+        }
+
+        /// Allows loans of an asset to be repaid in another asset, valued through the price oracle.
+        pub fn allow_alternative_repayment(
+            &mut self,
+            resource_address: ResourceAddress,
+            alternative: ResourceAddress,
+        ) {
+            assert!(
+                resource_address != alternative,
+                "Alternative must differ from the asset lent"
+            );
+            let pool = self.get_pool_mut(resource_address);
+            if !pool.allowed_alternatives.contains(&alternative) {
+                pool.allowed_alternatives.push(alternative);
+            }
+            pool.alternative_vaults
+                .entry(alternative)
+                .or_insert_with(|| Vault::new(alternative));
+            pool.alternative_protocol_fees
+                .entry(alternative)
+                .or_insert_with(|| Vault::new(alternative));
+        }
+
+        /// Stops accepting repayments of loans of an asset in another asset.  What was already received in it
+        /// remains redeemable by the liquidity providers.
+        pub fn disallow_alternative_repayment(
+            &mut self,
+            resource_address: ResourceAddress,
+            alternative: ResourceAddress,
+        ) {
+            self.get_pool_mut(resource_address)
+                .allowed_alternatives
+                .retain(|allowed| *allowed != alternative);
+        }
+
+        /// Returns the other assets loans of an asset may be repaid in.
+        pub fn get_allowed_alternatives(
+            &self,
+            resource_address: ResourceAddress,
+        ) -> Vec<ResourceAddress> {
+            self.get_pool(resource_address).allowed_alternatives.clone()
+        }
+
+        /// Returns the premium over the amount due charged on repayments in other assets.
+        pub fn get_alternative_premium(&self) -> Decimal {
+            self.alternative_premium
+        }
+
+        /// Returns the value of an asset's pool in that asset, including the repayments received in other assets.
+        /// These count at the value they settled rather than at the current oracle price, so that LP tokens can't
+        /// be minted off a price moved within the same transaction.
+        pub fn get_pool_value(&self, resource_address: ResourceAddress) -> Decimal {
+            let pool = self.get_pool(resource_address);
+            pool.loan_vault.amount() + pool.alternative_value
+        }
+
+        /// Returns the fee charged on loans of an asset.
+        pub fn get_fee(&self, resource_address: ResourceAddress) -> Decimal {
+            self.get_pool(resource_address).fee
+        }
+
+        /// Returns whether lending is paused.
+        pub fn is_paused(&self) -> bool {
+            self.paused
+        }
+
+        /// Returns the largest share of an asset's liquidity the loans outstanding at once may take.
+        pub fn get_max_loan_share(&self) -> Decimal {
+            self.max_loan_share
+        }
+
+        /// Returns the share of every loan fee which goes to the protocol.
+        pub fn get_protocol_fee_share(&self) -> Decimal {
+            self.protocol_fee_share
+        }
+
+        /// Returns the protocol fees accrued in an asset.
+        pub fn get_protocol_fees(&self, resource_address: ResourceAddress) -> Decimal {
+            self.get_pool(resource_address).protocol_fees.amount()
+        }
+
+        /// Returns the protocol fees accrued on loans of an asset repaid in another asset.
+        pub fn get_alternative_protocol_fees(
+            &self,
+            resource_address: ResourceAddress,
+            alternative: ResourceAddress,
+        ) -> Decimal {
+            match self
+                .get_pool(resource_address)
+                .alternative_protocol_fees
+                .get(&alternative)
+            {
+                Some(vault) => vault.amount(),
+                None => Decimal::zero(),
+            }
+        }
+
+        /// Returns the owner badge resource address.
+        pub fn owner_badge_address(&self) -> ResourceAddress {
+            self.owner_badge
+        }
+
+        /// Returns the price of BASE in QUOTE from the price oracle.
+        fn get_oracle_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Decimal {
+            let oracle: PriceOracleComponentTarget = self
+                .oracle_address
+                .expect("No price oracle has been set")
+                .into();
+            oracle
+                .get_price(base, quote)
+                .expect("No price available for the alternative repayment")
+        }
+
+        fn get_pool(&self, resource_address: ResourceAddress) -> &LoanPool {
+            match self.pools.get(&resource_address) {
+                Some(pool) => pool,
+                None => panic!("No pool for resource {:?}", resource_address),
+            }
+        }
+
+        fn get_pool_mut(&mut self, resource_address: ResourceAddress) -> &mut LoanPool {
+            match self.pools.get_mut(&resource_address) {
+                Some(pool) => pool,
+                None => panic!("No pool for resource {:?}", resource_address),
+            }
         }
     }
 }
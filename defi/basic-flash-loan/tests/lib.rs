@@ -0,0 +1,368 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    flash_loan: ComponentAddress,
+    transient_token: ResourceAddress,
+    owner_badge: ResourceAddress,
+    lp_token: ResourceAddress,
+    token: ResourceAddress,
+    alternative: ResourceAddress,
+}
+
+/// Sets up a BasicFlashLoan with 1,000 tokens of initial liquidity and the default 0.1% fee.
+fn setup() -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the asset to lend and another one to repay loans in
+    let token = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let alternative = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_default` function.
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account_component, token, dec!("1000"))
+        .take_from_worktop(token, |builder, bucket| {
+            builder.call_function(
+                package_address,
+                "BasicFlashLoan",
+                "instantiate_default",
+                manifest_args!(bucket),
+            )
+        })
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let flash_loan = receipt.expect_commit(true).new_component_addresses()[0];
+    let transient_token = receipt.expect_commit(true).new_resource_addresses()[1];
+    let owner_badge = receipt.expect_commit(true).new_resource_addresses()[2];
+    let lp_token = receipt.expect_commit(true).new_resource_addresses()[3];
+
+    TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        flash_loan,
+        transient_token,
+        owner_badge,
+        lp_token,
+        token,
+        alternative,
+    }
+}
+
+fn execute(env: &mut TestEnv, manifest: TransactionManifest) -> TransactionReceipt {
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Calls an owner method of the flash loan component, with the owner badge in the auth zone or not.
+fn call_as_owner(
+    env: &mut TestEnv,
+    with_owner_badge: bool,
+    method: &str,
+    args: Vec<u8>,
+) -> TransactionReceipt {
+    let mut builder = ManifestBuilder::new();
+    if with_owner_badge {
+        builder.create_proof_from_account(env.account_component, env.owner_badge);
+    }
+    let manifest = builder
+        .call_method(env.flash_loan, method, args)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+/// Borrows `loan_amount` tokens and repays `repayment` of REPAYMENT_RESOURCE, topping the loan up from the account.
+fn loan_and_repay(
+    env: &mut TestEnv,
+    loan_amount: Decimal,
+    repayment_resource: ResourceAddress,
+    repayment: Decimal,
+) -> TransactionReceipt {
+    let (token, transient_token) = (env.token, env.transient_token);
+    let top_up = if repayment_resource == token {
+        repayment - loan_amount
+    } else {
+        repayment
+    };
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.flash_loan,
+            "take_loan",
+            manifest_args!(token, loan_amount),
+        )
+        .withdraw_from_account(env.account_component, repayment_resource, top_up)
+        .take_from_worktop_by_amount(repayment, repayment_resource, |builder, bucket| {
+            builder.take_from_worktop(transient_token, |builder, terms| {
+                builder.call_method(env.flash_loan, "repay_loan", manifest_args!(bucket, terms))
+            })
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+/// Redeems all LP tokens and asserts at least `min_amount` tokens are returned.
+fn remove_all_liquidity(env: &mut TestEnv, min_amount: Decimal) -> TransactionReceipt {
+    let (lp_token, token) = (env.lp_token, env.token);
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, lp_token, dec!("1000"))
+        .take_from_worktop(lp_token, |builder, bucket| {
+            builder.call_method(env.flash_loan, "remove_liquidity", manifest_args!(bucket))
+        })
+        .assert_worktop_contains_by_amount(min_amount, token)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+#[test]
+fn test_loan_repaid_with_fee() {
+    let mut env = setup();
+    let token = env.token;
+
+    // 100 tokens at 0.1% are due back as 100.1
+    loan_and_repay(&mut env, dec!("100"), token, dec!("100.1")).expect_commit_success();
+    loan_and_repay(&mut env, dec!("100"), token, dec!("100.09")).expect_commit_failure();
+}
+
+#[test]
+fn test_fees_grow_lp_share() {
+    let mut env = setup();
+    let token = env.token;
+
+    loan_and_repay(&mut env, dec!("100"), token, dec!("100.1")).expect_commit_success();
+
+    // The only LP owns the whole pool, fee included
+    remove_all_liquidity(&mut env, dec!("1000.1")).expect_commit_success();
+}
+
+#[test]
+fn test_protocol_takes_its_share_of_fees() {
+    let mut env = setup();
+    let token = env.token;
+
+    call_as_owner(
+        &mut env,
+        true,
+        "set_protocol_fee_share",
+        manifest_args!(dec!("0.5")),
+    )
+    .expect_commit_success();
+    loan_and_repay(&mut env, dec!("100"), token, dec!("100.1")).expect_commit_success();
+
+    // Half of the 0.1 fee is the protocol's, the other half the LP's
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, env.owner_badge)
+        .call_method(
+            env.flash_loan,
+            "withdraw_protocol_fees",
+            manifest_args!(token),
+        )
+        .assert_worktop_contains_by_amount(dec!("0.05"), token)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+    remove_all_liquidity(&mut env, dec!("1000.06")).expect_commit_failure();
+    remove_all_liquidity(&mut env, dec!("1000.05")).expect_commit_success();
+}
+
+#[test]
+fn test_owner_methods_require_owner_badge() {
+    let mut env = setup();
+    let token = env.token;
+
+    call_as_owner(
+        &mut env,
+        false,
+        "set_fee",
+        manifest_args!(token, dec!("0.01")),
+    )
+    .expect_commit_failure();
+    call_as_owner(&mut env, false, "set_paused", manifest_args!(true)).expect_commit_failure();
+    call_as_owner(
+        &mut env,
+        false,
+        "withdraw_protocol_fees",
+        manifest_args!(token),
+    )
+    .expect_commit_failure();
+
+    // With the badge, a 1% fee makes 100.1 too little
+    call_as_owner(
+        &mut env,
+        true,
+        "set_fee",
+        manifest_args!(token, dec!("0.01")),
+    )
+    .expect_commit_success();
+    loan_and_repay(&mut env, dec!("100"), token, dec!("100.1")).expect_commit_failure();
+    loan_and_repay(&mut env, dec!("100"), token, dec!("101")).expect_commit_success();
+
+    // No loans while paused
+    call_as_owner(&mut env, true, "set_paused", manifest_args!(true)).expect_commit_success();
+    loan_and_repay(&mut env, dec!("100"), token, dec!("101")).expect_commit_failure();
+}
+
+/// Borrows 300 tokens twice in the same transaction, and repays both loans.
+fn take_two_loans(env: &mut TestEnv) -> TransactionReceipt {
+    let (token, transient_token) = (env.token, env.transient_token);
+    let mut builder = ManifestBuilder::new();
+    builder
+        .call_method(
+            env.flash_loan,
+            "take_loan",
+            manifest_args!(token, dec!("300")),
+        )
+        .call_method(
+            env.flash_loan,
+            "take_loan",
+            manifest_args!(token, dec!("300")),
+        )
+        .withdraw_from_account(env.account_component, token, dec!("0.6"));
+    for _ in 0..2 {
+        builder.take_from_worktop_by_amount(dec!("300.3"), token, |builder, bucket| {
+            builder.take_from_worktop_by_amount(dec!("1"), transient_token, |builder, terms| {
+                builder.call_method(env.flash_loan, "repay_loan", manifest_args!(bucket, terms))
+            })
+        });
+    }
+    let manifest = builder
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+#[test]
+fn test_max_loan_share_caps_outstanding_loans() {
+    let mut env = setup();
+    let token = env.token;
+
+    call_as_owner(
+        &mut env,
+        true,
+        "set_max_loan_share",
+        manifest_args!(dec!("0.5")),
+    )
+    .expect_commit_success();
+
+    // A single loan of half the liquidity is fine
+    loan_and_repay(&mut env, dec!("500"), token, dec!("500.5")).expect_commit_success();
+    loan_and_repay(&mut env, dec!("501"), token, dec!("501.501")).expect_commit_failure();
+
+    // Two loans of 300 are more than half of the 1,000 liquidity together, even though either is less than half of
+    // what is left after the other
+    take_two_loans(&mut env).expect_commit_failure();
+
+    call_as_owner(
+        &mut env,
+        true,
+        "set_max_loan_share",
+        manifest_args!(dec!("0.6")),
+    )
+    .expect_commit_success();
+    take_two_loans(&mut env).expect_commit_success();
+}
+
+#[test]
+fn test_repayment_in_alternative_asset_is_valued_by_oracle() {
+    let mut env = setup();
+    let (account_component, token, alternative) =
+        (env.account_component, env.token, env.alternative);
+
+    // Publish the price oracle next to this package, and price the alternative at 2 tokens
+    let oracle_package_address = env
+        .test_runner
+        .compile_and_publish(concat!(env!("CARGO_MANIFEST_DIR"), "/../price-oracle"));
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            oracle_package_address,
+            "PriceOracle",
+            "instantiate_oracle",
+            manifest_args!(1u32, 1u32, 10u64, dec!("0.1")),
+        )
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = execute(&mut env, manifest);
+    receipt.expect_commit_success();
+    let price_oracle = receipt.expect_commit(true).new_component_addresses()[0];
+    let reporter_badge = receipt.expect_commit(true).new_resource_addresses()[0];
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(account_component, reporter_badge)
+        .create_proof_from_auth_zone(reporter_badge, |builder, proof| {
+            builder.call_method(
+                price_oracle,
+                "update_price",
+                manifest_args!(proof, alternative, token, dec!("2")),
+            )
+        })
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+
+    call_as_owner(&mut env, true, "set_oracle", manifest_args!(price_oracle))
+        .expect_commit_success();
+
+    // Not accepted until the owner allows it
+    loan_and_repay(&mut env, dec!("100"), alternative, dec!("51")).expect_commit_failure();
+    call_as_owner(
+        &mut env,
+        true,
+        "allow_alternative_repayment",
+        manifest_args!(token, alternative),
+    )
+    .expect_commit_success();
+
+    // 100.1 tokens due, plus the 1% premium, at 2 tokens per alternative: 50.5505
+    loan_and_repay(&mut env, dec!("100"), alternative, dec!("50.55")).expect_commit_failure();
+    loan_and_repay(&mut env, dec!("100"), alternative, dec!("50.5505")).expect_commit_success();
+}
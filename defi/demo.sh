@@ -58,7 +58,7 @@ resim mint 114841533.01 $snx --proofs 1,$acc1_mint_badge
 # publish PriceOracle
 price_oracle_package=package_sim1q9nmp3gffream9we6wtzywce82ezku488s9c5ekuzgcsvz6tmy
 resim publish ./price-oracle --package-address $price_oracle_package
-out=`resim call-function $price_oracle_package PriceOracle instantiate_oracle 1 | tee /dev/tty | awk '/Component:|Resource:/ {print $NF}'`
+out=`resim call-function $price_oracle_package PriceOracle instantiate_oracle 1 1 100 0.5 | tee /dev/tty | awk '/Component:|Resource:/ {print $NF}'`
 price_oracle_component=`echo $out | cut -d " " -f1`
 price_oracle_update_auth=`echo $out | cut -d " " -f2`
 
@@ -74,12 +74,14 @@ auto_lend_component=`resim call-function $auto_lend_package AutoLend instantiate
 # publish Synthetics
 synthetics_package=package_sim1q9dakzhyhnx8yhgra8kmq5xzxvafcqau8vkyqtctjyhqpd4ncq
 resim publish ./synthetics --package-address $synthetics_package
-synthetics_component=`resim call-function $synthetics_package SyntheticPool instantiate_pool $price_oracle_component $snx $usd 4 | tee /dev/tty | awk '/Component:/ {print $NF}'`
+out=`resim call-function $synthetics_package SyntheticPool instantiate_pool $price_oracle_component $snx $usd 4 | tee /dev/tty | awk '/Component:|Resource:/ {print $NF}'`
+synthetics_component=`echo $out | cut -d " " -f1`
+synthetics_admin_badge=`echo $out | cut -d " " -f2`
 
 # publish xPerpFutures
 perpetual_futures_package=package_sim1qyw7eufp4ht322umgqpz5flpdlkdeh24jhzwt9rh7c0srml70a
 resim publish ./x-perp-futures --package-address $perpetual_futures_package
-perpetual_futures_component=`resim call-function $perpetual_futures_package ClearingHouse instantiate_clearing_house $usd 100 45 | tee /dev/tty | awk '/Component:/ {print $NF}'`
+perpetual_futures_component=`resim call-function $perpetual_futures_package ClearingHouse instantiate_clearing_house $usd 100 45 $price_oracle_component $xrd 12 | tee /dev/tty | awk '/Component:/ {print $NF}'`
 
 # Set up swap pools
 xrd_snx_radiswap_component=`resim call-function $radiswap_package Radiswap instantiate_pool 1000000,$xrd 38271,$snx 1000000 LPT LPToken https://www.example.com/ 0.001 | tee /dev/tty | awk '/Component:/ {print $NF}'`
@@ -87,18 +89,29 @@ xrd_snx_radiswap_component=`resim call-function $radiswap_package Radiswap insta
 # Update price
 echo "CALL_METHOD ComponentAddress(\"$acc1_address\") \"lock_fee\" Decimal(\"10\");" > tx.rtm
 echo "CALL_METHOD ComponentAddress(\"$acc1_address\") \"create_proof\" ResourceAddress(\"$price_oracle_update_auth\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$btc\") ResourceAddress(\"$usd\") Decimal(\"57523\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$eth\") ResourceAddress(\"$usd\") Decimal(\"3763\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$btc\") ResourceAddress(\"$gbp\") Decimal(\"41950\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$eth\") ResourceAddress(\"$gbp\") Decimal(\"2746\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$btc\") ResourceAddress(\"$eth\") Decimal(\"15\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$xrd\") ResourceAddress(\"$usd\") Decimal(\"0.4\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$snx\") ResourceAddress(\"$usd\") Decimal(\"10.40\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$btc\") ResourceAddress(\"$usd\") Decimal(\"66050.98\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter1\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter1\") ResourceAddress(\"$btc\") ResourceAddress(\"$usd\") Decimal(\"57523\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter2\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter2\") ResourceAddress(\"$eth\") ResourceAddress(\"$usd\") Decimal(\"3763\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter3\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter3\") ResourceAddress(\"$btc\") ResourceAddress(\"$gbp\") Decimal(\"41950\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter4\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter4\") ResourceAddress(\"$eth\") ResourceAddress(\"$gbp\") Decimal(\"2746\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter5\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter5\") ResourceAddress(\"$btc\") ResourceAddress(\"$eth\") Decimal(\"15\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter6\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter6\") ResourceAddress(\"$xrd\") ResourceAddress(\"$usd\") Decimal(\"0.4\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter7\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter7\") ResourceAddress(\"$snx\") ResourceAddress(\"$usd\") Decimal(\"10.40\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter8\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter8\") ResourceAddress(\"$btc\") ResourceAddress(\"$usd\") Decimal(\"66050.98\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"add_hub_asset\" ResourceAddress(\"$usd\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"add_hub_asset\" ResourceAddress(\"$xrd\");" >> tx.rtm
 resim run tx.rtm
 rm tx.rtm
 resim call-method $price_oracle_component get_price $btc $eth
 resim call-method $price_oracle_component get_price $eth $btc
+resim call-method $price_oracle_component get_price_route $snx $xrd
 
 # Summary
 set +x
@@ -127,6 +140,7 @@ echo "AutoLend blueprint: $auto_lend_package AutoLend"
 echo "AutoLend component: $auto_lend_component"
 echo "Synthetics blueprint: $synthetics_package SyntheticPool"
 echo "Synthetics component: $synthetics_component"
+echo "Synthetics admin badge: $synthetics_admin_badge"
 echo "xPerpFutures blueprint: $perpetual_futures_package ClearingHouse"
 echo "xPerpFutures component: $perpetual_futures_component"
 echo "XRD/SNX swap: $xrd_snx_radiswap_component"
@@ -141,11 +155,16 @@ set -x
 tesla=`resim new-token-fixed --name "Tesla Token" --symbol "TESLA" 0 | tee /dev/tty | awk '/Resource:/ {print $NF}'`
 echo "CALL_METHOD ComponentAddress(\"$acc1_address\") \"lock_fee\" Decimal(\"10\");" > tx.rtm
 echo "CALL_METHOD ComponentAddress(\"$acc1_address\") \"create_proof\" ResourceAddress(\"$price_oracle_update_auth\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$tesla\") ResourceAddress(\"$usd\") Decimal(\"1162.00\");" >> tx.rtm
-echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" ResourceAddress(\"$xrd\") ResourceAddress(\"$snx\") Decimal(\"0.03901819\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter9\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter9\") ResourceAddress(\"$tesla\") ResourceAddress(\"$usd\") Decimal(\"1162.00\");" >> tx.rtm
+echo "CREATE_PROOF_FROM_AUTH_ZONE ResourceAddress(\"$price_oracle_update_auth\") Proof(\"reporter10\");" >> tx.rtm
+echo "CALL_METHOD ComponentAddress(\"$price_oracle_component\") \"update_price\" Proof(\"reporter10\") ResourceAddress(\"$xrd\") ResourceAddress(\"$snx\") Decimal(\"0.03901819\");" >> tx.rtm
 resim run tx.rtm
 rm tx.rtm
 
+# List sTESLA in the synthetic pool
+resim call-method $synthetics_component add_synthetic_token "TESLA" $tesla --proofs 1,$synthetics_admin_badge
+
 # Publish mutual farm package
 mutual_farm_package=`resim publish mutual-farm | tee /dev/tty | awk '/Package:/ {print $NF}'`
 
@@ -13,7 +13,7 @@ use scrypto::prelude::*;
 
 external_blueprint! {
   PriceOraclePackageTarget {
-    fn instantiate_oracle(num_of_admins: u32) -> (Bucket, ComponentAddress);
+    fn instantiate_oracle(num_of_admins: u32, quorum: u32, max_age: u64, max_deviation: Decimal) -> (Bucket, Bucket, ComponentAddress);
 
   }
 }
@@ -21,20 +21,19 @@ external_blueprint! {
 external_component! {
     PriceOracleComponentTarget {
         fn get_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Option<Decimal>;
-        fn update_price(&self, base: ResourceAddress, quote: ResourceAddress, price: Decimal);
+        fn update_price(&self, reporter: Proof, base: ResourceAddress, quote: ResourceAddress, price: Decimal);
         fn admin_badge_address(&self) -> ResourceAddress;
     }
 }
 
 external_blueprint! {
   SyntheticPoolPackageTarget {
-    fn instantiate_pool(oracle_address: ComponentAddress, snx_token_address: ResourceAddress, usd_token_address: ResourceAddress, collateralization_threshold: Decimal) -> ComponentAddress;
+    fn instantiate_pool(oracle_address: ComponentAddress, snx_token_address: ResourceAddress, usd_token_address: ResourceAddress, collateralization_threshold: Decimal) -> (ComponentAddress, Bucket);
   }
 }
 
 external_component! {
     SyntheticPoolComponentTarget {
-        fn add_synthetic_token(&mut self,asset_symbol: String,asset_address: ResourceAddress) -> ResourceAddress;
         fn stake(&mut self, user_auth: Proof, stake_in_snx: Bucket);
         fn unstake(&mut self, user_auth: Proof, amount: Decimal) -> Bucket;
         fn mint(&mut self, user_auth: Proof, amount: Decimal, symbol: String) -> Bucket;
@@ -42,14 +41,14 @@ external_component! {
         fn get_total_global_debt(&self) -> Decimal;
         fn get_snx_price(&self) -> Decimal;
         fn get_asset_price(&self, asset_address: ResourceAddress) -> Decimal;
-        fn get_user_summary(&mut self, user_id: ResourceAddress) -> String;
+        fn get_user_summary(&mut self, user_id: NonFungibleLocalId) -> String;
         fn new_user(&self) -> Bucket;
     }
 }
 
 external_blueprint! {
   RadiswapPackageTarget {
-    fn instantiate_pool(a_tokens: Bucket, b_tokens: Bucket, lp_initial_supply: Decimal, lp_symbol: String, lp_name: String, lp_url: String, fee: Decimal) -> (ComponentAddress, Bucket);
+    fn instantiate_pool(a_tokens: Bucket, b_tokens: Bucket, lp_initial_supply: Decimal, lp_symbol: String, lp_name: String, lp_url: String, fee: Decimal) -> (ComponentAddress, Bucket, Bucket);
   }
 }
 
@@ -73,6 +72,8 @@ mod mutual_farm {
         price_oracle: PriceOracleComponentTarget,
         /// Synthetic for minting synthetic tokens
         synthetic_pool: SyntheticPoolComponentTarget,
+        /// Badge identifying this farm as a synthetic pool user
+        synthetics_user_badge: Vault,
 
         /// Asset symbol
         asset_symbol: String,
@@ -89,6 +90,8 @@ mod mutual_farm {
         radiswap: RadiswapComponentTarget,
         /// Radiswap LP token vault
         radiswap_lp_tokens: Vault,
+        /// Radiswap owner badge vault
+        radiswap_owner_badge: Vault,
 
         /// Mutual farm share resource address
         mutual_farm_share_resource_address: ResourceAddress,
@@ -130,22 +133,22 @@ mod mutual_farm {
             let snx = xrd_snx_radiswap.swap(initial_xrd.take(initial_xrd.amount() * 3 / 4));
             let snx_amount = snx.amount();
 
-            debug!("Deposit SNX into synthetic pool and mint sTESLA (1/10 of our SNX).");
+            debug!("Deposit SNX into synthetic pool and mint sTESLA (1/10 of our SNX), which the pool admin must have listed.");
             let price_oracle: PriceOracleComponentTarget = price_oracle_address.into();
             let mut synthetic_pool: SyntheticPoolComponentTarget = synthetic_pool_address.into();
-            synthetic_pool.add_synthetic_token(asset_symbol.clone(), asset_address);
-            synthetic_pool.stake(identity_badge.create_proof(), snx);
+            let synthetics_user_badge = synthetic_pool.new_user();
+            synthetic_pool.stake(synthetics_user_badge.create_proof(), snx);
 
             let quantity = snx_amount * snx_usd_price / 10 / tesla_usd_price;
             let synth = synthetic_pool.mint(
-                identity_badge.create_proof(),
+                synthetics_user_badge.create_proof(),
                 quantity,
                 asset_symbol.clone(),
             );
             let synth_address = synth.resource_address();
 
             debug!("Set up sTESLA/XRD swap pool");
-            let (radiswap_comp, lp_tokens) =
+            let (radiswap_comp, lp_tokens, radiswap_owner_badge) =
                 RadiswapPackageTarget::at(radiswap_package_address, "Radiswap").instantiate_pool(
                     synth,
                     initial_xrd,
@@ -173,6 +176,7 @@ mod mutual_farm {
                 price_oracle,
                 xrd_snx_radiswap,
                 synthetic_pool,
+                synthetics_user_badge: Vault::with_bucket(synthetics_user_badge),
                 asset_symbol,
                 asset_address,
                 synth_address,
@@ -180,6 +184,7 @@ mod mutual_farm {
                 usd_address,
                 radiswap: radiswap_comp.into(),
                 radiswap_lp_tokens: Vault::with_bucket(lp_tokens),
+                radiswap_owner_badge: Vault::with_bucket(radiswap_owner_badge),
                 mutual_farm_share_resource_address,
                 total_contribution_in_usd: xrd_amount * xrd_usd_price,
             }
@@ -214,10 +219,10 @@ mod mutual_farm {
 
             debug!("Deposit SNX into synthetic pool and mint sTESLA (1/10 of our SNX).");
             self.synthetic_pool
-                .stake(self.identity_badge.create_proof(), snx);
+                .stake(self.synthetics_user_badge.create_proof(), snx);
             let quantity = snx_amount * snx_usd_price / dec!("10") / tesla_usd_price;
             let synth = self.synthetic_pool.mint(
-                self.identity_badge.create_proof(),
+                self.synthetics_user_badge.create_proof(),
                 quantity,
                 self.asset_symbol.clone(),
             );
@@ -226,7 +231,7 @@ mod mutual_farm {
             let (lp_tokens, mut remainder) = self.radiswap.add_liquidity(synth, xrd);
             if remainder.resource_address() == self.synth_address {
                 self.synthetic_pool
-                    .burn(self.identity_badge.create_proof(), remainder);
+                    .burn(self.synthetics_user_badge.create_proof(), remainder);
                 remainder = Bucket::new(xrd_address);
             }
             self.radiswap_lp_tokens.put(lp_tokens);
@@ -1,59 +1,564 @@
 use scrypto::prelude::*;
 
+external_component! {
+    PriceOracleComponentTarget {
+        fn get_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Option<Decimal>;
+        fn admin_badge_address(&self) -> ResourceAddress;
+    }
+}
+
+external_component! {
+    RadiswapComponentTarget {
+        fn consult(&self, base: ResourceAddress, quote: ResourceAddress, window_epochs: u64) -> Option<Decimal>;
+    }
+}
+
+/// The number of accepted reports kept in the history of each resource pair.
+const HISTORY_CAPACITY: usize = 32;
+
+#[derive(NonFungibleData, ScryptoSbor)]
+pub struct Reporter {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, ScryptoSbor)]
+pub struct PriceReport {
+    /// The price reported
+    pub price: Decimal,
+    /// The epoch it was reported at
+    pub epoch: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, ScryptoSbor)]
+pub enum PriceSource {
+    /// The median of the reports pushed by the admins of this oracle
+    AdminPush,
+    /// The price of another `PriceOracle` component
+    Oracle(ComponentAddress),
+    /// The time-weighted average price of a Radiswap pool over a window of epochs
+    AmmTwap {
+        component: ComponentAddress,
+        window_epochs: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, ScryptoSbor)]
+pub struct PriceQuote {
+    /// The price of the base in the quote
+    pub price: Decimal,
+    /// The resources the price was derived through, from base to quote
+    pub route: Vec<ResourceAddress>,
+    /// The epoch of the oldest report the price was derived from
+    pub epoch: u64,
+}
+
 #[blueprint]
 mod price_oracle {
     struct PriceOracle {
-        /// Last price of each resource pair
-        prices: KeyValueStore<(ResourceAddress, ResourceAddress), Decimal>,
-        /// The admin badge resource def address
+        /// Latest report of every reporter, for each resource pair
+        reports: KeyValueStore<
+            (ResourceAddress, ResourceAddress),
+            HashMap<NonFungibleLocalId, PriceReport>,
+        >,
+        /// The admin badge resource def address, each badge being a distinct reporter
         admin_badge: ResourceAddress,
+        /// The owner badge resource def address, for configuring the feeds
+        owner_badge: ResourceAddress,
+        /// The number of fresh reports needed for a price
+        quorum: u32,
+        /// The number of epochs after which a report is no longer fresh
+        max_age: u64,
+        /// The accepted reports of each resource pair, as (epoch, price), oldest first
+        history: KeyValueStore<(ResourceAddress, ResourceAddress), Vec<(u64, Decimal)>>,
+        /// The first median of each resource pair in each epoch, kept for good
+        first_prices: KeyValueStore<(ResourceAddress, ResourceAddress, u64), Decimal>,
+        /// The largest relative move from the last accepted price a report may make without being rejected, and
+        /// from the previous median the median may make without pausing the feed
+        max_deviation: Decimal,
+        /// The resource pairs whose feed is paused
+        paused: KeyValueStore<(ResourceAddress, ResourceAddress), bool>,
+        /// The resources cross rates are derived through when a pair has no direct price, in order of preference
+        hub_assets: Vec<ResourceAddress>,
+        /// The sources of each resource pair, in fallback order. Pairs not listed use admin push only.
+        sources: KeyValueStore<(ResourceAddress, ResourceAddress), Vec<PriceSource>>,
     }
 
     impl PriceOracle {
-        /// Creates a PriceOracle component, along with admin badges.
-        pub fn instantiate_oracle(num_of_admins: u32) -> (Bucket, ComponentAddress) {
+        /// Creates a PriceOracle component, along with admin badges for reporting prices and an owner badge for
+        /// configuring the feeds.
+        pub fn instantiate_oracle(
+            num_of_admins: u32,
+            quorum: u32,
+            max_age: u64,
+            max_deviation: Decimal,
+        ) -> (Bucket, Bucket, ComponentAddress) {
             assert!(num_of_admins >= 1);
+            assert!(
+                quorum >= 1 && quorum <= num_of_admins,
+                "Quorum must be between 1 and the number of admins"
+            );
+            assert!(max_deviation > Decimal::zero(), "Invalid max deviation");
 
-            let badges = ResourceBuilder::new_fungible()
-                .divisibility(DIVISIBILITY_NONE)
+            let badges = ResourceBuilder::new_integer_non_fungible()
                 .metadata("name", "Price Oracle Admin Badge")
-                .mint_initial_supply(num_of_admins);
+                .mint_initial_supply((1..=num_of_admins).map(|i| {
+                    (
+                        IntegerNonFungibleLocalId::new(i as u64),
+                        Reporter {
+                            name: format!("Reporter {}", i),
+                        },
+                    )
+                }));
 
+            // Reporters may only report prices, how the reports are used is up to the owner
+            let owner_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "Price Oracle Owner Badge")
+                .mint_initial_supply(1);
             let rules = AccessRulesConfig::new()
                 .method(
                     "update_price",
                     rule!(require(badges.resource_address())),
                     AccessRule::DenyAll,
                 )
+                .method(
+                    "unpause",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_max_deviation",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "add_hub_asset",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "remove_hub_asset",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_price_sources",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
                 .default(rule!(allow_all), AccessRule::DenyAll);
 
             let component = Self {
-                prices: KeyValueStore::new(),
+                reports: KeyValueStore::new(),
                 admin_badge: badges.resource_address(),
+                owner_badge: owner_badge.resource_address(),
+                quorum,
+                max_age,
+                history: KeyValueStore::new(),
+                first_prices: KeyValueStore::new(),
+                max_deviation,
+                paused: KeyValueStore::new(),
+                hub_assets: Vec::new(),
+                sources: KeyValueStore::new(),
             }
             .instantiate();
             let component_address = component.globalize_with_access_rules(rules);
 
-            (badges, component_address)
+            (badges, owner_badge, component_address)
         }
 
-        /// Returns the current price of a resource pair BASE/QUOTE.
+        /// Returns the price of a resource pair BASE/QUOTE, derived through a hub asset if there is no direct price.
         pub fn get_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Option<Decimal> {
-            match self.prices.get(&(base, quote)) {
-                Some(price) => Some(*price),
-                None => None,
+            self.get_price_route(base, quote).map(|quote| quote.price)
+        }
+
+        /// Returns the price of a resource pair BASE/QUOTE along with the route it was derived through, or `None`
+        /// if there is neither a direct price nor one through any hub asset.
+        pub fn get_price_route(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Option<PriceQuote> {
+            if let Some(direct_quote) = self.get_direct_quote(base, quote) {
+                return Some(direct_quote);
+            }
+
+            // Derive a cross rate through the first hub asset both legs are priced against
+            for hub in self.hub_assets.iter() {
+                if *hub == base || *hub == quote {
+                    continue;
+                }
+                let first_leg = self.get_direct_quote(base, *hub);
+                let second_leg = self.get_direct_quote(*hub, quote);
+                if let (Some(first_leg), Some(second_leg)) = (first_leg, second_leg) {
+                    return Some(PriceQuote {
+                        price: first_leg.price * second_leg.price,
+                        route: vec![base, *hub, quote],
+                        epoch: first_leg.epoch.min(second_leg.epoch),
+                    });
+                }
+            }
+
+            None
+        }
+
+        /// Returns the latest report of every reporter for a resource pair BASE/QUOTE, fresh or not.
+        pub fn get_reports(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> HashMap<NonFungibleLocalId, PriceReport> {
+            match self.reports.get(&(base, quote)) {
+                Some(reports) => reports.clone(),
+                None => HashMap::new(),
+            }
+        }
+
+        /// Reports the price of a resource pair BASE/QUOTE and its inverse, replacing the reporter's previous report.
+        /// A report which moves the price by more than the max deviation from the last accepted one is rejected, and
+        /// a report which moves the median by more than it pauses the feed. To follow a genuine move that large,
+        /// raise the max deviation first.
+        pub fn update_price(
+            &self,
+            reporter: Proof,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+            price: Decimal,
+        ) {
+            let reporter: ValidatedProof = reporter
+                .validate_proof(ProofValidationMode::ValidateContainsAmount(
+                    self.admin_badge,
+                    dec!("1"),
+                ))
+                .expect("Invalid reporter proof");
+            assert!(price > Decimal::zero(), "Price must be positive");
+            assert!(
+                !self.is_paused(base, quote),
+                "The price feed of {:?}/{:?} is paused",
+                base,
+                quote
+            );
+
+            // Check against the history rather than the fresh reports, which may have gone stale or been cleared
+            let history = self.get_history(base, quote);
+            if let Some((_, last_price)) = history.last() {
+                assert!(
+                    (price - *last_price).abs() / *last_price <= self.max_deviation,
+                    "Report of {} deviates too much from the last accepted price {}",
+                    price,
+                    last_price
+                );
+            }
+            let previous_quote = self.get_admin_quote(base, quote);
+
+            let reporter_id = reporter.non_fungible_local_id();
+            let epoch = Runtime::current_epoch();
+            self.insert_report(
+                base,
+                quote,
+                reporter_id.clone(),
+                PriceReport { price, epoch },
+            );
+            self.insert_report(
+                quote,
+                base,
+                reporter_id,
+                PriceReport {
+                    price: dec!("1") / price,
+                    epoch,
+                },
+            );
+            self.record_history(base, quote, epoch, price);
+            self.record_history(quote, base, epoch, dec!("1") / price);
+
+            // Pause the feed rather than fail if the median moved too much, so that the pause sticks
+            if let (Some(previous_quote), Some(current_quote)) =
+                (previous_quote, self.get_admin_quote(base, quote))
+            {
+                let (previous_price, current_price) = (previous_quote.price, current_quote.price);
+                if (current_price - previous_price).abs() / previous_price > self.max_deviation {
+                    info!(
+                        "Median of {} deviates too much from {}, pausing the feed",
+                        current_price, previous_price
+                    );
+                    self.paused.insert((base, quote), true);
+                    self.paused.insert((quote, base), true);
+                }
+            }
+            self.record_first_price(base, quote, epoch);
+            self.record_first_price(quote, base, epoch);
+        }
+
+        /// Returns the accepted reports of a resource pair BASE/QUOTE, as (epoch, price), oldest first.
+        pub fn get_history(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Vec<(u64, Decimal)> {
+            match self.history.get(&(base, quote)) {
+                Some(history) => history.clone(),
+                None => Vec::new(),
+            }
+        }
+
+        /// Returns the first median of a resource pair BASE/QUOTE in an epoch, if the reports of that epoch met the
+        /// quorum without pausing the feed. Unlike the current price, it never changes once set, so that trades can be
+        /// settled at it later.
+        pub fn get_first_price(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+            epoch: u64,
+        ) -> Option<Decimal> {
+            self.first_prices
+                .get(&(base, quote, epoch))
+                .map(|price| *price)
+        }
+
+        /// Returns whether the feed of a resource pair BASE/QUOTE is paused.
+        pub fn is_paused(&self, base: ResourceAddress, quote: ResourceAddress) -> bool {
+            match self.paused.get(&(base, quote)) {
+                Some(paused) => *paused,
+                None => false,
             }
         }
 
-        /// Updates the price of a resource pair BASE/QUOTE and its inverse.
-        pub fn update_price(&self, base: ResourceAddress, quote: ResourceAddress, price: Decimal) {
-            self.prices.insert((base, quote), price);
-            self.prices.insert((quote, base), dec!("1") / price);
+        /// Resumes the feed of a resource pair BASE/QUOTE. The reports are cleared, so that the median is taken
+        /// from the next ones only, which are still checked against the last accepted price.
+        pub fn unpause(&self, base: ResourceAddress, quote: ResourceAddress) {
+            self.paused.insert((base, quote), false);
+            self.paused.insert((quote, base), false);
+            self.reports.insert((base, quote), HashMap::new());
+            self.reports.insert((quote, base), HashMap::new());
+        }
+
+        /// Sets the largest relative move from the last accepted price a report may make without being rejected.
+        pub fn set_max_deviation(&mut self, max_deviation: Decimal) {
+            assert!(max_deviation > Decimal::zero(), "Invalid max deviation");
+            self.max_deviation = max_deviation;
+        }
+
+        /// Returns the largest relative move from the last accepted price a report may make without being rejected.
+        pub fn get_max_deviation(&self) -> Decimal {
+            self.max_deviation
         }
 
         /// Returns the admin badge resource address.
         pub fn admin_badge_address(&self) -> ResourceAddress {
             self.admin_badge
         }
+
+        /// Returns the owner badge resource address.
+        pub fn owner_badge_address(&self) -> ResourceAddress {
+            self.owner_badge
+        }
+
+        /// Returns the number of fresh reports needed for a price.
+        pub fn get_quorum(&self) -> u32 {
+            self.quorum
+        }
+
+        /// Returns the number of epochs after which a report is no longer fresh.
+        pub fn get_max_age(&self) -> u64 {
+            self.max_age
+        }
+
+        /// Registers a resource cross rates may be derived through, after the ones already registered.
+        pub fn add_hub_asset(&mut self, hub: ResourceAddress) {
+            assert!(
+                !self.hub_assets.contains(&hub),
+                "Hub asset {:?} is already registered",
+                hub
+            );
+            self.hub_assets.push(hub);
+        }
+
+        /// Stops deriving cross rates through a resource.
+        pub fn remove_hub_asset(&mut self, hub: ResourceAddress) {
+            self.hub_assets.retain(|hub_asset| *hub_asset != hub);
+        }
+
+        /// Returns the resources cross rates are derived through, in order of preference.
+        pub fn get_hub_assets(&self) -> Vec<ResourceAddress> {
+            self.hub_assets.clone()
+        }
+
+        /// Sets the sources of a resource pair BASE/QUOTE and its inverse, in fallback order.
+        pub fn set_price_sources(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+            sources: Vec<PriceSource>,
+        ) {
+            assert!(!sources.is_empty(), "At least one price source is required");
+            for source in sources.iter() {
+                if let PriceSource::Oracle(component) = source {
+                    // Admin badges are unique to each oracle, so a match means the source is this very oracle
+                    let oracle: PriceOracleComponentTarget = (*component).into();
+                    assert!(
+                        oracle.admin_badge_address() != self.admin_badge,
+                        "A price source can't point back at this oracle"
+                    );
+                }
+            }
+            self.sources.insert((base, quote), sources.clone());
+            self.sources.insert((quote, base), sources);
+        }
+
+        /// Returns the sources of a resource pair BASE/QUOTE, in fallback order.
+        pub fn get_price_sources(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Vec<PriceSource> {
+            match self.sources.get(&(base, quote)) {
+                Some(sources) => sources.clone(),
+                None => vec![PriceSource::AdminPush],
+            }
+        }
+
+        /// Returns whether a resource pair BASE/QUOTE is currently priced by the reports of the admins alone, which
+        /// are the only prices recorded as the first price of an epoch.
+        pub fn is_admin_priced(&self, base: ResourceAddress, quote: ResourceAddress) -> bool {
+            self.get_price_sources(base, quote) == vec![PriceSource::AdminPush]
+                && self.get_admin_quote(base, quote).is_some()
+        }
+
+        /// Returns the first source of a resource pair BASE/QUOTE which currently has a price, or `None` if none has.
+        pub fn price_source(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Option<PriceSource> {
+            self.get_price_sources(base, quote)
+                .into_iter()
+                .find(|source| self.get_source_quote(source, base, quote).is_some())
+        }
+
+        /// Returns the price of a resource pair BASE/QUOTE from the first of its sources which has one.
+        fn get_direct_quote(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Option<PriceQuote> {
+            self.get_price_sources(base, quote)
+                .iter()
+                .find_map(|source| self.get_source_quote(source, base, quote))
+        }
+
+        /// Returns the price of a resource pair BASE/QUOTE from a single source.
+        fn get_source_quote(
+            &self,
+            source: &PriceSource,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Option<PriceQuote> {
+            // External sources apply their own staleness rules, so their price counts as current
+            let price = match source {
+                PriceSource::AdminPush => return self.get_admin_quote(base, quote),
+                PriceSource::Oracle(component) => {
+                    let oracle: PriceOracleComponentTarget = (*component).into();
+                    oracle.get_price(base, quote)?
+                }
+                PriceSource::AmmTwap {
+                    component,
+                    window_epochs,
+                } => {
+                    let radiswap: RadiswapComponentTarget = (*component).into();
+                    radiswap.consult(base, quote, *window_epochs)?
+                }
+            };
+
+            Some(PriceQuote {
+                price,
+                route: vec![base, quote],
+                epoch: Runtime::current_epoch(),
+            })
+        }
+
+        /// Returns the median of the fresh reports for a resource pair BASE/QUOTE, along with the epoch of the
+        /// oldest of them, or `None` if there are fewer than the quorum or the feed is paused.
+        fn get_admin_quote(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Option<PriceQuote> {
+            if self.is_paused(base, quote) {
+                return None;
+            }
+
+            let reports = self.get_fresh_reports(base, quote);
+            if reports.len() < self.quorum as usize {
+                return None;
+            }
+
+            let mut prices: Vec<Decimal> = reports.iter().map(|report| report.price).collect();
+            prices.sort();
+            let middle = prices.len() / 2;
+            let price = if prices.len() % 2 == 1 {
+                prices[middle]
+            } else {
+                (prices[middle - 1] + prices[middle]) / 2
+            };
+
+            Some(PriceQuote {
+                price,
+                route: vec![base, quote],
+                epoch: reports.iter().map(|report| report.epoch).min().unwrap(),
+            })
+        }
+
+        /// Returns the reports for a resource pair BASE/QUOTE which are no older than the max age.
+        fn get_fresh_reports(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+        ) -> Vec<PriceReport> {
+            let current_epoch = Runtime::current_epoch();
+            self.get_reports(base, quote)
+                .into_values()
+                .filter(|report| current_epoch - report.epoch <= self.max_age)
+                .collect()
+        }
+
+        /// Replaces the report of a reporter for a resource pair BASE/QUOTE.
+        fn insert_report(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+            reporter_id: NonFungibleLocalId,
+            report: PriceReport,
+        ) {
+            let mut reports = self.get_reports(base, quote);
+            reports.insert(reporter_id, report);
+            self.reports.insert((base, quote), reports);
+        }
+
+        /// Records the median of a resource pair BASE/QUOTE as the first price of an epoch, unless it already has one.
+        fn record_first_price(&self, base: ResourceAddress, quote: ResourceAddress, epoch: u64) {
+            if self.first_prices.get(&(base, quote, epoch)).is_some() {
+                return;
+            }
+            if let Some(current_quote) = self.get_admin_quote(base, quote) {
+                self.first_prices
+                    .insert((base, quote, epoch), current_quote.price);
+            }
+        }
+
+        /// Appends an accepted report to the history of a resource pair BASE/QUOTE, dropping the oldest if full.
+        fn record_history(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+            epoch: u64,
+            price: Decimal,
+        ) {
+            let mut history = self.get_history(base, quote);
+            if history.len() >= HISTORY_CAPACITY {
+                history.remove(0);
+            }
+            history.push((epoch, price));
+            self.history.insert((base, quote), history);
+        }
     }
 }
@@ -0,0 +1,266 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+/// Mirrors `PriceSource`, so that sources can be passed in a manifest.
+#[derive(ManifestSbor)]
+enum ManifestPriceSource {
+    AdminPush,
+    Oracle(ComponentAddress),
+}
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    price_oracle: ComponentAddress,
+    admin_badge: ResourceAddress,
+    owner_badge: ResourceAddress,
+    base: ResourceAddress,
+    quote: ResourceAddress,
+}
+
+/// Sets up a PriceOracle with a single admin, a quorum of 1, a max age of 10 epochs and a max deviation of 10%.
+fn setup() -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the resources to price
+    let base = test_runner.create_fungible_resource(dec!("1000"), 18, account_component);
+    let quote = test_runner.create_fungible_resource(dec!("1000"), 18, account_component);
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_oracle` function.
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            package_address,
+            "PriceOracle",
+            "instantiate_oracle",
+            manifest_args!(1u32, 1u32, 10u64, dec!("0.1")),
+        )
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let price_oracle = receipt.expect_commit(true).new_component_addresses()[0];
+    let admin_badge = receipt.expect_commit(true).new_resource_addresses()[0];
+    let owner_badge = receipt.expect_commit(true).new_resource_addresses()[1];
+
+    TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        price_oracle,
+        admin_badge,
+        owner_badge,
+        base,
+        quote,
+    }
+}
+
+/// Calls a method of the oracle with the given badge in the auth zone.
+fn call_with_badge(
+    env: &mut TestEnv,
+    badge: ResourceAddress,
+    method: &str,
+    args: Vec<u8>,
+) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, badge)
+        .call_method(env.price_oracle, method, args)
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Reports the price of the base in the quote.
+fn update_price(env: &mut TestEnv, price: Decimal) -> TransactionReceipt {
+    let (base, quote) = (env.base, env.quote);
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, env.admin_badge)
+        .create_proof_from_auth_zone(env.admin_badge, |builder, proof| {
+            builder.call_method(
+                env.price_oracle,
+                "update_price",
+                manifest_args!(proof, base, quote, price),
+            )
+        })
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+#[test]
+fn test_report_within_max_deviation_is_accepted() {
+    let mut env = setup();
+
+    update_price(&mut env, dec!("100")).expect_commit_success();
+    update_price(&mut env, dec!("109")).expect_commit_success();
+}
+
+#[test]
+fn test_outlier_report_is_rejected_without_pausing() {
+    let mut env = setup();
+
+    update_price(&mut env, dec!("100")).expect_commit_success();
+    update_price(&mut env, dec!("150")).expect_commit_failure();
+
+    // The feed is not paused, so reports close to the last accepted price still go through
+    update_price(&mut env, dec!("105")).expect_commit_success();
+}
+
+#[test]
+fn test_outlier_report_is_rejected_when_feed_is_stale() {
+    let mut env = setup();
+
+    env.test_runner.set_current_epoch(1);
+    update_price(&mut env, dec!("100")).expect_commit_success();
+
+    // The only report is past the max age, so there is no current price, but the history still has it
+    env.test_runner.set_current_epoch(100);
+    update_price(&mut env, dec!("150")).expect_commit_failure();
+    update_price(&mut env, dec!("105")).expect_commit_success();
+}
+
+#[test]
+fn test_outlier_report_is_rejected_after_unpause() {
+    let mut env = setup();
+    let (base, quote) = (env.base, env.quote);
+
+    update_price(&mut env, dec!("100")).expect_commit_success();
+
+    // Unpausing clears the reports, but not the history they are checked against
+    let owner_badge = env.owner_badge;
+    call_with_badge(
+        &mut env,
+        owner_badge,
+        "unpause",
+        manifest_args!(base, quote),
+    )
+    .expect_commit_success();
+    update_price(&mut env, dec!("150")).expect_commit_failure();
+    update_price(&mut env, dec!("105")).expect_commit_success();
+}
+
+#[test]
+fn test_raising_max_deviation_lets_a_large_move_through() {
+    let mut env = setup();
+
+    update_price(&mut env, dec!("100")).expect_commit_success();
+    let owner_badge = env.owner_badge;
+    call_with_badge(
+        &mut env,
+        owner_badge,
+        "set_max_deviation",
+        manifest_args!(dec!("0.6")),
+    )
+    .expect_commit_success();
+    update_price(&mut env, dec!("150")).expect_commit_success();
+}
+
+#[test]
+fn test_first_price_of_an_epoch_is_kept() {
+    let mut env = setup();
+    let (base, quote) = (env.base, env.quote);
+
+    env.test_runner.set_current_epoch(5);
+    update_price(&mut env, dec!("100")).expect_commit_success();
+    update_price(&mut env, dec!("105")).expect_commit_success();
+
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.price_oracle,
+            "get_first_price",
+            manifest_args!(base, quote, 5u64),
+        )
+        .build();
+    let receipt = env
+        .test_runner
+        .execute_manifest_ignoring_fee(manifest, vec![]);
+    println!("{:?}\n", receipt);
+    let first_price: Option<Decimal> = receipt.expect_commit(true).output(0);
+    assert_eq!(first_price, Some(dec!("100")));
+}
+
+#[test]
+fn test_reporter_cannot_configure_feeds() {
+    let mut env = setup();
+    let (admin_badge, base, quote) = (env.admin_badge, env.base, env.quote);
+
+    update_price(&mut env, dec!("100")).expect_commit_success();
+    call_with_badge(
+        &mut env,
+        admin_badge,
+        "set_max_deviation",
+        manifest_args!(dec!("0.6")),
+    )
+    .expect_commit_failure();
+    call_with_badge(
+        &mut env,
+        admin_badge,
+        "unpause",
+        manifest_args!(base, quote),
+    )
+    .expect_commit_failure();
+    call_with_badge(&mut env, admin_badge, "add_hub_asset", manifest_args!(base))
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_reporter_cannot_reroute_pair() {
+    let mut env = setup();
+    let (admin_badge, owner_badge, base, quote) =
+        (env.admin_badge, env.owner_badge, env.base, env.quote);
+
+    call_with_badge(
+        &mut env,
+        admin_badge,
+        "set_price_sources",
+        manifest_args!(base, quote, vec![ManifestPriceSource::AdminPush]),
+    )
+    .expect_commit_failure();
+    call_with_badge(
+        &mut env,
+        owner_badge,
+        "set_price_sources",
+        manifest_args!(base, quote, vec![ManifestPriceSource::AdminPush]),
+    )
+    .expect_commit_success();
+}
+
+#[test]
+fn test_price_source_pointing_back_is_rejected() {
+    let mut env = setup();
+    let (owner_badge, price_oracle, base, quote) =
+        (env.owner_badge, env.price_oracle, env.base, env.quote);
+
+    call_with_badge(
+        &mut env,
+        owner_badge,
+        "set_price_sources",
+        manifest_args!(base, quote, vec![ManifestPriceSource::Oracle(price_oracle)]),
+    )
+    .expect_commit_failure();
+}
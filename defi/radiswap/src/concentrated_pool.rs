@@ -0,0 +1,840 @@
+use crate::math::{div_up, mul_up, powi, sqrt};
+use scrypto::prelude::*;
+
+/// The lowest tick a position may start at, i.e. a price of about 2e-9.
+const MIN_TICK: i32 = -200_000;
+
+/// The highest tick a position may end at, i.e. a price of about 4.8e8.
+const MAX_TICK: i32 = 200_000;
+
+/// Returns sqrt(1.0001), the ratio between the square root prices of two adjacent ticks.
+fn sqrt_tick_ratio() -> Decimal {
+    dec!("1.000049998750062496")
+}
+
+#[derive(NonFungibleData, ScryptoSbor)]
+pub struct LiquidityPosition {
+    /// The tick the price range of the position starts at
+    pub lower_tick: i32,
+    /// The tick the price range of the position ends at
+    pub upper_tick: i32,
+    /// The liquidity the position provides within its range
+    pub liquidity: Decimal,
+    /// The fee growth per unit of liquidity within the range of the position, in token A, when fees were last
+    /// accounted for
+    #[mutable]
+    pub fee_growth_inside_a_last: Decimal,
+    /// The fee growth per unit of liquidity within the range of the position, in token B, when fees were last
+    /// accounted for
+    #[mutable]
+    pub fee_growth_inside_b_last: Decimal,
+    /// The fees earned in token A which have not been collected yet
+    #[mutable]
+    pub fees_owed_a: Decimal,
+    /// The fees earned in token B which have not been collected yet
+    #[mutable]
+    pub fees_owed_b: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, ScryptoSbor)]
+pub struct Tick {
+    /// The total liquidity of the positions starting or ending at this tick
+    pub liquidity_gross: Decimal,
+    /// The liquidity added when the price crosses this tick upwards, or removed when it crosses downwards
+    pub liquidity_net: Decimal,
+    /// The fee growth in token A on the other side of this tick, relative to the current price
+    pub fee_growth_outside_a: Decimal,
+    /// The fee growth in token B on the other side of this tick, relative to the current price
+    pub fee_growth_outside_b: Decimal,
+}
+
+/// The outcome of a swap, computed without changing the pool.
+struct SwapResult {
+    /// The amount of the other token returned
+    output_amount: Decimal,
+    /// The part of the input charged as fee
+    fee_amount: Decimal,
+    /// The square root price after the swap
+    sqrt_price: Decimal,
+    /// The tick after the swap
+    tick: i32,
+    /// The active liquidity after the swap
+    liquidity: Decimal,
+    /// The global fee growth of the input token after the swap
+    fee_growth_global: Decimal,
+    /// The ticks crossed, along with the global fee growth of the input token when each was crossed
+    crossed_ticks: Vec<(i32, Decimal)>,
+}
+
+#[blueprint]
+mod concentrated_pool {
+    /// A pool where each liquidity provider chooses the price range their liquidity is used in, as in Uniswap v3.
+    /// Prices are expressed in B per A and split into ticks, each 0.01% apart. Positions are non-fungible tokens
+    /// which earn fees only while the price is within their range.
+    struct ConcentratedPool {
+        /// The reserve for token A.
+        a_pool: Vault,
+        /// The reserve for token B.
+        b_pool: Vault,
+        /// The fees earned by positions in token A.
+        fees_a: Vault,
+        /// The fees earned by positions in token B.
+        fees_b: Vault,
+        /// The fee to apply for every swap
+        fee: Decimal,
+        /// Positions may only start and end at multiples of this tick spacing.
+        tick_spacing: i32,
+        /// The square root of the current price.
+        sqrt_price: Decimal,
+        /// The tick the current price is in.
+        current_tick: i32,
+        /// The liquidity of all positions whose range contains the current price.
+        liquidity: Decimal,
+        /// The fees earned in token A per unit of liquidity, since the pool was created.
+        fee_growth_global_a: Decimal,
+        /// The fees earned in token B per unit of liquidity, since the pool was created.
+        fee_growth_global_b: Decimal,
+        /// The ticks which positions start or end at, or used to.
+        ticks: KeyValueStore<i32, Tick>,
+        /// One bit per tick spacing, set if a position starts or ends at that tick, in words of 128 keyed by their
+        /// position. Swaps search it for the next tick to cross one word at a time.
+        tick_bitmap: KeyValueStore<i32, u128>,
+        /// The resource address of the position non-fungibles.
+        position_resource_address: ResourceAddress,
+        /// Position mint badge.
+        position_mint_badge: Vault,
+        /// The ID of the next position minted.
+        next_position_id: u64,
+    }
+
+    impl ConcentratedPool {
+        /// Creates a ConcentratedPool component for token pair A/B starting at the given price of A in B,
+        /// and returns the component address.
+        pub fn instantiate_pool(
+            a_address: ResourceAddress,
+            b_address: ResourceAddress,
+            initial_price: Decimal,
+            fee: Decimal,
+            tick_spacing: i32,
+        ) -> ComponentAddress {
+            // Check arguments
+            assert!(a_address != b_address, "The pair must be of two resources");
+            assert!(
+                fee >= dec!("0") && fee < dec!("1"),
+                "Invalid fee in thousandths"
+            );
+            assert!(tick_spacing > 0, "Invalid tick spacing");
+            let sqrt_price = sqrt(initial_price);
+            assert!(
+                sqrt_price >= sqrt_price_at_tick(MIN_TICK)
+                    && sqrt_price < sqrt_price_at_tick(MAX_TICK),
+                "Initial price out of range"
+            );
+
+            // Instantiate our position non-fungibles, whose fee data only we may update
+            let position_mint_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "Position Mint Auth")
+                .mint_initial_supply(1);
+            let position_resource_address =
+                ResourceBuilder::new_integer_non_fungible::<LiquidityPosition>()
+                    .metadata("name", "Concentrated Liquidity Position")
+                    .mintable(
+                        rule!(require(position_mint_badge.resource_address())),
+                        LOCKED,
+                    )
+                    .burnable(
+                        rule!(require(position_mint_badge.resource_address())),
+                        LOCKED,
+                    )
+                    .updateable_non_fungible_data(
+                        rule!(require(position_mint_badge.resource_address())),
+                        LOCKED,
+                    )
+                    .create_with_no_initial_supply();
+
+            Self {
+                a_pool: Vault::new(a_address),
+                b_pool: Vault::new(b_address),
+                fees_a: Vault::new(a_address),
+                fees_b: Vault::new(b_address),
+                fee,
+                tick_spacing,
+                sqrt_price,
+                current_tick: tick_at_sqrt_price(sqrt_price),
+                liquidity: Decimal::zero(),
+                fee_growth_global_a: Decimal::zero(),
+                fee_growth_global_b: Decimal::zero(),
+                ticks: KeyValueStore::new(),
+                tick_bitmap: KeyValueStore::new(),
+                position_resource_address,
+                position_mint_badge: Vault::with_bucket(position_mint_badge),
+                next_position_id: 1,
+            }
+            .instantiate()
+            .globalize()
+        }
+
+        /// Provides as much liquidity as the given tokens allow within the price range between the two ticks.
+        /// Returns the position non-fungible along with the remainder of token A and B.
+        pub fn add_position(
+            &mut self,
+            mut a_tokens: Bucket,
+            mut b_tokens: Bucket,
+            lower_tick: i32,
+            upper_tick: i32,
+        ) -> (Bucket, Bucket, Bucket) {
+            assert!(
+                a_tokens.resource_address() == self.a_pool.resource_address()
+                    && b_tokens.resource_address() == self.b_pool.resource_address(),
+                "Wrong token type passed in"
+            );
+            assert!(
+                lower_tick < upper_tick && lower_tick >= MIN_TICK && upper_tick <= MAX_TICK,
+                "Invalid tick range"
+            );
+            assert!(
+                lower_tick % self.tick_spacing == 0 && upper_tick % self.tick_spacing == 0,
+                "Ticks must be multiples of the tick spacing {}",
+                self.tick_spacing
+            );
+
+            // Calculate the liquidity the tokens provide and how much of them that takes
+            let sqrt_lower = sqrt_price_at_tick(lower_tick);
+            let sqrt_upper = sqrt_price_at_tick(upper_tick);
+            let liquidity = liquidity_for_amounts(
+                self.sqrt_price,
+                sqrt_lower,
+                sqrt_upper,
+                a_tokens.amount(),
+                b_tokens.amount(),
+            );
+            assert!(
+                liquidity > Decimal::zero(),
+                "Not enough tokens to provide liquidity in this range"
+            );
+            // Rounded up, which never exceeds the given amounts as the liquidity was rounded down
+            let (a_amount, b_amount) =
+                amounts_for_liquidity(self.sqrt_price, sqrt_lower, sqrt_upper, liquidity, true);
+            self.a_pool.put(a_tokens.take(a_amount));
+            self.b_pool.put(b_tokens.take(b_amount));
+
+            // Register the liquidity at both ends of the range, and activate it if the range contains the price
+            self.update_tick(lower_tick, liquidity, false);
+            self.update_tick(upper_tick, liquidity, true);
+            if self.current_tick >= lower_tick && self.current_tick < upper_tick {
+                self.liquidity += liquidity;
+            }
+
+            // Mint the position, which earns the fees from now on
+            let (fee_growth_inside_a, fee_growth_inside_b) =
+                self.get_fee_growth_inside(lower_tick, upper_tick);
+            let position_id = NonFungibleLocalId::integer(self.next_position_id);
+            self.next_position_id += 1;
+            let position = self.position_mint_badge.authorize(|| {
+                borrow_resource_manager!(self.position_resource_address).mint_non_fungible(
+                    &position_id,
+                    LiquidityPosition {
+                        lower_tick,
+                        upper_tick,
+                        liquidity,
+                        fee_growth_inside_a_last: fee_growth_inside_a,
+                        fee_growth_inside_b_last: fee_growth_inside_b,
+                        fees_owed_a: Decimal::zero(),
+                        fees_owed_b: Decimal::zero(),
+                    },
+                )
+            });
+
+            (position, a_tokens, b_tokens)
+        }
+
+        /// Closes a position, returning its liquidity and uncollected fees in token A and B.
+        pub fn remove_position(&mut self, position: Bucket) -> (Bucket, Bucket) {
+            assert!(
+                position.resource_address() == self.position_resource_address,
+                "Wrong token type passed in"
+            );
+            let position_id = position.non_fungible_local_id();
+            let data: LiquidityPosition = position.non_fungible().data();
+
+            // Collect the fees before the liquidity goes away
+            let (mut a_tokens, mut b_tokens) = self.collect_position_fees(&position_id);
+
+            // Remove the liquidity at both ends of the range, and deactivate it if the range contains the price
+            self.update_tick(data.lower_tick, -data.liquidity, false);
+            self.update_tick(data.upper_tick, -data.liquidity, true);
+            if self.current_tick >= data.lower_tick && self.current_tick < data.upper_tick {
+                self.liquidity -= data.liquidity;
+            }
+
+            // Withdraw the tokens the liquidity is worth at the current price, rounded down
+            let (a_amount, b_amount) = amounts_for_liquidity(
+                self.sqrt_price,
+                sqrt_price_at_tick(data.lower_tick),
+                sqrt_price_at_tick(data.upper_tick),
+                data.liquidity,
+                false,
+            );
+            a_tokens.put(self.a_pool.take(a_amount));
+            b_tokens.put(self.b_pool.take(b_amount));
+
+            // Burn the position received
+            self.position_mint_badge.authorize(|| {
+                position.burn();
+            });
+
+            (a_tokens, b_tokens)
+        }
+
+        /// Collects the fees a position has earned so far in token A and B.
+        pub fn collect_fees(&mut self, position_proof: Proof) -> (Bucket, Bucket) {
+            let position_proof: ValidatedProof = position_proof
+                .validate_proof(ProofValidationMode::ValidateContainsAmount(
+                    self.position_resource_address,
+                    dec!("1"),
+                ))
+                .expect("Invalid position proof");
+
+            self.collect_position_fees(&position_proof.non_fungible_local_id())
+        }
+
+        /// Records the fees a position has earned so far into its data, so they show up as uncollected fees.
+        pub fn update_position_fees(&mut self, position_proof: Proof) {
+            let position_proof: ValidatedProof = position_proof
+                .validate_proof(ProofValidationMode::ValidateContainsAmount(
+                    self.position_resource_address,
+                    dec!("1"),
+                ))
+                .expect("Invalid position proof");
+
+            self.accrue_position_fees(&position_proof.non_fungible_local_id());
+        }
+
+        /// Swaps token A for B, or vice versa, crossing as many ticks as needed.
+        pub fn swap(&mut self, mut input_tokens: Bucket) -> Bucket {
+            let input_is_a = self.is_token_a(input_tokens.resource_address());
+            let result = self.compute_swap(input_is_a, input_tokens.amount());
+
+            // Flip the fee growth outside every tick crossed, so it stays relative to the new price
+            let (fee_growth_global_a, fee_growth_global_b) =
+                (self.fee_growth_global_a, self.fee_growth_global_b);
+            for (tick, fee_growth_global) in result.crossed_ticks {
+                let mut tick = self.ticks.get_mut(&tick).unwrap();
+                if input_is_a {
+                    tick.fee_growth_outside_a = fee_growth_global - tick.fee_growth_outside_a;
+                    tick.fee_growth_outside_b = fee_growth_global_b - tick.fee_growth_outside_b;
+                } else {
+                    tick.fee_growth_outside_a = fee_growth_global_a - tick.fee_growth_outside_a;
+                    tick.fee_growth_outside_b = fee_growth_global - tick.fee_growth_outside_b;
+                }
+            }
+
+            // Move the price
+            self.sqrt_price = result.sqrt_price;
+            self.current_tick = result.tick;
+            self.liquidity = result.liquidity;
+
+            // Put the fee aside and the rest of the input tokens into our pool, and return the tokens owed, which
+            // were rounded down
+            let fee_tokens = input_tokens.take(result.fee_amount);
+            if input_is_a {
+                self.fee_growth_global_a = result.fee_growth_global;
+                self.fees_a.put(fee_tokens);
+                self.a_pool.put(input_tokens);
+                self.b_pool.take(result.output_amount)
+            } else {
+                self.fee_growth_global_b = result.fee_growth_global;
+                self.fees_b.put(fee_tokens);
+                self.b_pool.put(input_tokens);
+                self.a_pool.take(result.output_amount)
+            }
+        }
+
+        /// Returns how much of the other token `swap` would return for the given input.
+        pub fn get_amount_out(
+            &self,
+            input_resource: ResourceAddress,
+            input_amount: Decimal,
+        ) -> Decimal {
+            self.compute_swap(self.is_token_a(input_resource), input_amount)
+                .output_amount
+        }
+
+        /// Returns the resource addresses of the pair.
+        pub fn get_pair(&self) -> (ResourceAddress, ResourceAddress) {
+            (
+                self.a_pool.resource_address(),
+                self.b_pool.resource_address(),
+            )
+        }
+
+        /// Returns the current price of A in B.
+        pub fn get_spot_price(&self) -> Decimal {
+            self.sqrt_price * self.sqrt_price
+        }
+
+        /// Returns the tick the current price is in.
+        pub fn get_current_tick(&self) -> i32 {
+            self.current_tick
+        }
+
+        /// Returns the liquidity of all positions whose range contains the current price.
+        pub fn get_liquidity(&self) -> Decimal {
+            self.liquidity
+        }
+
+        /// Returns the fees a position has earned so far in token A and B, including those not yet recorded in its
+        /// data.
+        pub fn get_uncollected_fees(&self, position_id: NonFungibleLocalId) -> (Decimal, Decimal) {
+            let position: LiquidityPosition =
+                borrow_resource_manager!(self.position_resource_address)
+                    .get_non_fungible_data(&position_id);
+            let (fee_growth_inside_a, fee_growth_inside_b) =
+                self.get_fee_growth_inside(position.lower_tick, position.upper_tick);
+
+            (
+                position.fees_owed_a
+                    + position.liquidity
+                        * (fee_growth_inside_a - position.fee_growth_inside_a_last),
+                position.fees_owed_b
+                    + position.liquidity
+                        * (fee_growth_inside_b - position.fee_growth_inside_b_last),
+            )
+        }
+
+        /// Returns the resource address of the position non-fungibles.
+        pub fn get_position_resource_address(&self) -> ResourceAddress {
+            self.position_resource_address
+        }
+
+        /// Returns true if RESOURCE is token A, false if it is token B.
+        fn is_token_a(&self, resource: ResourceAddress) -> bool {
+            if resource == self.a_pool.resource_address() {
+                true
+            } else if resource == self.b_pool.resource_address() {
+                false
+            } else {
+                panic!("Resource {:?} is not part of this pair", resource);
+            }
+        }
+
+        /// Adds (or removes, if negative) liquidity to a tick at one end of a position.
+        fn update_tick(&mut self, tick: i32, liquidity_delta: Decimal, is_upper: bool) {
+            let initialized = self
+                .ticks
+                .get(&tick)
+                .map(|info| !info.liquidity_gross.is_zero())
+                .unwrap_or(false);
+            if !initialized {
+                // By convention, all fees so far were earned below a tick at or under the current one
+                let info = if tick <= self.current_tick {
+                    Tick {
+                        liquidity_gross: Decimal::zero(),
+                        liquidity_net: Decimal::zero(),
+                        fee_growth_outside_a: self.fee_growth_global_a,
+                        fee_growth_outside_b: self.fee_growth_global_b,
+                    }
+                } else {
+                    Tick {
+                        liquidity_gross: Decimal::zero(),
+                        liquidity_net: Decimal::zero(),
+                        fee_growth_outside_a: Decimal::zero(),
+                        fee_growth_outside_b: Decimal::zero(),
+                    }
+                };
+                self.ticks.insert(tick, info);
+                self.flip_tick(tick);
+            }
+
+            let uninitialized = {
+                let mut info = self.ticks.get_mut(&tick).unwrap();
+                info.liquidity_gross += liquidity_delta;
+                info.liquidity_net += if is_upper {
+                    -liquidity_delta
+                } else {
+                    liquidity_delta
+                };
+                info.liquidity_gross.is_zero()
+            };
+
+            // Unset ticks no position starts or ends at anymore, so swaps skip them
+            if uninitialized {
+                self.flip_tick(tick);
+            }
+        }
+
+        /// Sets the bit of a tick in the bitmap if it is unset, or unsets it otherwise.
+        fn flip_tick(&mut self, tick: i32) {
+            let (word_position, bit_position) = bitmap_position(tick.div_euclid(self.tick_spacing));
+            let word = self.get_bitmap_word(word_position);
+            self.tick_bitmap
+                .insert(word_position, word ^ (1u128 << bit_position));
+        }
+
+        /// Returns the next tick a position starts or ends at, searching from the given tick downwards (including
+        /// it) or upwards (excluding it) within a single bitmap word. If there is none, returns the last tick of the
+        /// word along with false.
+        fn next_tick_within_one_word(&self, tick: i32, downwards: bool) -> (i32, bool) {
+            let compressed = tick.div_euclid(self.tick_spacing);
+            if downwards {
+                let (word_position, bit_position) = bitmap_position(compressed);
+                // All the bits at or below the current one
+                let mask = if bit_position == 127 {
+                    u128::MAX
+                } else {
+                    (1u128 << (bit_position + 1)) - 1
+                };
+                let masked = self.get_bitmap_word(word_position) & mask;
+                let next_bit = if masked != 0 {
+                    127 - masked.leading_zeros() as i32
+                } else {
+                    0
+                };
+                (
+                    (word_position * 128 + next_bit) * self.tick_spacing,
+                    masked != 0,
+                )
+            } else {
+                let (word_position, bit_position) = bitmap_position(compressed + 1);
+                // All the bits at or above the one after the current one
+                let masked = self.get_bitmap_word(word_position) & (u128::MAX << bit_position);
+                let next_bit = if masked != 0 {
+                    masked.trailing_zeros() as i32
+                } else {
+                    127
+                };
+                (
+                    (word_position * 128 + next_bit) * self.tick_spacing,
+                    masked != 0,
+                )
+            }
+        }
+
+        /// Returns a word of the tick bitmap, which is empty if no tick in it was ever set.
+        fn get_bitmap_word(&self, word_position: i32) -> u128 {
+            self.tick_bitmap
+                .get(&word_position)
+                .map(|word| *word)
+                .unwrap_or(0)
+        }
+
+        /// Returns the fees earned per unit of liquidity within the range between the two ticks, in token A and B.
+        fn get_fee_growth_inside(&self, lower_tick: i32, upper_tick: i32) -> (Decimal, Decimal) {
+            let lower = self.ticks.get(&lower_tick).unwrap().clone();
+            let upper = self.ticks.get(&upper_tick).unwrap().clone();
+
+            let (below_a, below_b) = if self.current_tick >= lower_tick {
+                (lower.fee_growth_outside_a, lower.fee_growth_outside_b)
+            } else {
+                (
+                    self.fee_growth_global_a - lower.fee_growth_outside_a,
+                    self.fee_growth_global_b - lower.fee_growth_outside_b,
+                )
+            };
+            let (above_a, above_b) = if self.current_tick < upper_tick {
+                (upper.fee_growth_outside_a, upper.fee_growth_outside_b)
+            } else {
+                (
+                    self.fee_growth_global_a - upper.fee_growth_outside_a,
+                    self.fee_growth_global_b - upper.fee_growth_outside_b,
+                )
+            };
+
+            (
+                self.fee_growth_global_a - below_a - above_a,
+                self.fee_growth_global_b - below_b - above_b,
+            )
+        }
+
+        /// Records the fees a position has earned since they were last accounted for into its data.
+        fn accrue_position_fees(&mut self, position_id: &NonFungibleLocalId) -> LiquidityPosition {
+            let resource_manager = borrow_resource_manager!(self.position_resource_address);
+            let mut position: LiquidityPosition =
+                resource_manager.get_non_fungible_data(position_id);
+            let (fee_growth_inside_a, fee_growth_inside_b) =
+                self.get_fee_growth_inside(position.lower_tick, position.upper_tick);
+
+            position.fees_owed_a +=
+                position.liquidity * (fee_growth_inside_a - position.fee_growth_inside_a_last);
+            position.fees_owed_b +=
+                position.liquidity * (fee_growth_inside_b - position.fee_growth_inside_b_last);
+            position.fee_growth_inside_a_last = fee_growth_inside_a;
+            position.fee_growth_inside_b_last = fee_growth_inside_b;
+
+            self.position_mint_badge.authorize(|| {
+                let mut resource_manager = borrow_resource_manager!(self.position_resource_address);
+                resource_manager.update_non_fungible_data(
+                    position_id,
+                    "fee_growth_inside_a_last",
+                    fee_growth_inside_a,
+                );
+                resource_manager.update_non_fungible_data(
+                    position_id,
+                    "fee_growth_inside_b_last",
+                    fee_growth_inside_b,
+                );
+                resource_manager.update_non_fungible_data(
+                    position_id,
+                    "fees_owed_a",
+                    position.fees_owed_a,
+                );
+                resource_manager.update_non_fungible_data(
+                    position_id,
+                    "fees_owed_b",
+                    position.fees_owed_b,
+                );
+            });
+
+            position
+        }
+
+        /// Takes all fees a position has earned out of the fee vaults.
+        fn collect_position_fees(&mut self, position_id: &NonFungibleLocalId) -> (Bucket, Bucket) {
+            let position = self.accrue_position_fees(position_id);
+
+            self.position_mint_badge.authorize(|| {
+                let mut resource_manager = borrow_resource_manager!(self.position_resource_address);
+                resource_manager.update_non_fungible_data(
+                    position_id,
+                    "fees_owed_a",
+                    Decimal::zero(),
+                );
+                resource_manager.update_non_fungible_data(
+                    position_id,
+                    "fees_owed_b",
+                    Decimal::zero(),
+                );
+            });
+
+            // The fees owed are rounded down, so the fee vaults always cover them
+            (
+                self.fees_a.take(position.fees_owed_a),
+                self.fees_b.take(position.fees_owed_b),
+            )
+        }
+
+        /// Computes a swap step by step, one initialized tick at a time, without changing the pool.
+        fn compute_swap(&self, input_is_a: bool, input_amount: Decimal) -> SwapResult {
+            let mut sqrt_price = self.sqrt_price;
+            let mut tick = self.current_tick;
+            let mut liquidity = self.liquidity;
+            let mut fee_growth_global = if input_is_a {
+                self.fee_growth_global_a
+            } else {
+                self.fee_growth_global_b
+            };
+            let mut remaining = input_amount;
+            let mut output_amount = Decimal::zero();
+            let mut fee_amount = Decimal::zero();
+            let mut crossed_ticks = Vec::new();
+
+            while remaining > Decimal::zero() {
+                // Selling A moves the price down, selling B moves it up, until the next initialized tick or the end of
+                // the bitmap word, whichever comes first
+                let (next_tick, initialized) = self.next_tick_within_one_word(tick, input_is_a);
+                let (next_tick, initialized) = if next_tick <= MIN_TICK {
+                    (MIN_TICK, initialized && next_tick == MIN_TICK)
+                } else if next_tick >= MAX_TICK {
+                    (MAX_TICK, initialized && next_tick == MAX_TICK)
+                } else {
+                    (next_tick, initialized)
+                };
+                let sqrt_target = sqrt_price_at_tick(next_tick);
+
+                // The input (after fee) it takes to move the price to the next tick, rounded up, and the output
+                // released, rounded down
+                let (max_input, max_output) = if input_is_a {
+                    (
+                        amount_a_delta(sqrt_target, sqrt_price, liquidity, true),
+                        amount_b_delta(sqrt_target, sqrt_price, liquidity, false),
+                    )
+                } else {
+                    (
+                        amount_b_delta(sqrt_price, sqrt_target, liquidity, true),
+                        amount_a_delta(sqrt_price, sqrt_target, liquidity, false),
+                    )
+                };
+                let available_input = remaining * (dec!("1") - self.fee);
+
+                if available_input >= max_input {
+                    // Move the price all the way to the next tick
+                    let gross_input = div_up(max_input, dec!("1") - self.fee);
+                    let step_fee = gross_input - max_input;
+                    remaining -= gross_input;
+                    output_amount += max_output;
+                    fee_amount += step_fee;
+                    if liquidity > Decimal::zero() {
+                        fee_growth_global += step_fee / liquidity;
+                    }
+                    sqrt_price = sqrt_target;
+
+                    if initialized {
+                        // Cross the tick, activating or deactivating the positions starting or ending there
+                        let liquidity_net = self.ticks.get(&next_tick).unwrap().liquidity_net;
+                        crossed_ticks.push((next_tick, fee_growth_global));
+                        if input_is_a {
+                            liquidity -= liquidity_net;
+                        } else {
+                            liquidity += liquidity_net;
+                        }
+                    } else if next_tick == MIN_TICK || next_tick == MAX_TICK {
+                        assert!(
+                            remaining <= Decimal::zero(),
+                            "Not enough liquidity to complete the swap"
+                        );
+                    }
+                    tick = if input_is_a { next_tick - 1 } else { next_tick };
+                } else {
+                    // Move the price within the current range, using up the input. The price moves by less than the
+                    // exact amount, so the output is rounded down.
+                    let new_sqrt_price = if input_is_a {
+                        div_up(
+                            mul_up(liquidity, sqrt_price),
+                            liquidity + available_input * sqrt_price,
+                        )
+                    } else {
+                        sqrt_price + available_input / liquidity
+                    };
+                    output_amount += if input_is_a {
+                        amount_b_delta(new_sqrt_price, sqrt_price, liquidity, false)
+                    } else {
+                        amount_a_delta(sqrt_price, new_sqrt_price, liquidity, false)
+                    };
+                    let step_fee = remaining - available_input;
+                    fee_amount += step_fee;
+                    fee_growth_global += step_fee / liquidity;
+                    sqrt_price = new_sqrt_price;
+                    tick = tick_at_sqrt_price(sqrt_price);
+                    remaining = Decimal::zero();
+                }
+            }
+
+            SwapResult {
+                output_amount,
+                fee_amount,
+                sqrt_price,
+                tick,
+                liquidity,
+                fee_growth_global,
+                crossed_ticks,
+            }
+        }
+    }
+}
+
+/// Returns the square root of the price at a tick, i.e. sqrt(1.0001 ^ tick).
+fn sqrt_price_at_tick(tick: i32) -> Decimal {
+    let sqrt_price = powi(sqrt_tick_ratio(), tick.unsigned_abs());
+    if tick < 0 {
+        Decimal::one() / sqrt_price
+    } else {
+        sqrt_price
+    }
+}
+
+/// Returns the highest tick whose square root price is at or below the given one.
+fn tick_at_sqrt_price(sqrt_price: Decimal) -> i32 {
+    let mut low = MIN_TICK;
+    let mut high = MAX_TICK;
+    while low < high {
+        let middle = low + (high - low + 1) / 2;
+        if sqrt_price_at_tick(middle) <= sqrt_price {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    low
+}
+
+/// Returns the word and bit of a tick in the tick bitmap, given the tick divided by the tick spacing.
+fn bitmap_position(compressed_tick: i32) -> (i32, u32) {
+    (
+        compressed_tick.div_euclid(128),
+        compressed_tick.rem_euclid(128) as u32,
+    )
+}
+
+/// Returns the most liquidity the given amounts of A and B provide within a range, at the current price, rounded
+/// down.
+fn liquidity_for_amounts(
+    sqrt_price: Decimal,
+    sqrt_lower: Decimal,
+    sqrt_upper: Decimal,
+    a_amount: Decimal,
+    b_amount: Decimal,
+) -> Decimal {
+    // The products of square root prices are computed as in `amount_a_delta`, so rounding the amounts for this
+    // liquidity up gives back at most the given amounts
+    if sqrt_price <= sqrt_lower {
+        // The range is above the price, so it only holds A
+        a_amount * (sqrt_lower * sqrt_upper) / (sqrt_upper - sqrt_lower)
+    } else if sqrt_price >= sqrt_upper {
+        // The range is below the price, so it only holds B
+        b_amount / (sqrt_upper - sqrt_lower)
+    } else {
+        let a_liquidity = a_amount * (sqrt_price * sqrt_upper) / (sqrt_upper - sqrt_price);
+        let b_liquidity = b_amount / (sqrt_price - sqrt_lower);
+        a_liquidity.min(b_liquidity)
+    }
+}
+
+/// Returns the amounts of A and B some liquidity is worth within a range, at the current price, rounded up or down.
+fn amounts_for_liquidity(
+    sqrt_price: Decimal,
+    sqrt_lower: Decimal,
+    sqrt_upper: Decimal,
+    liquidity: Decimal,
+    round_up: bool,
+) -> (Decimal, Decimal) {
+    if sqrt_price <= sqrt_lower {
+        (
+            amount_a_delta(sqrt_lower, sqrt_upper, liquidity, round_up),
+            Decimal::zero(),
+        )
+    } else if sqrt_price >= sqrt_upper {
+        (
+            Decimal::zero(),
+            amount_b_delta(sqrt_lower, sqrt_upper, liquidity, round_up),
+        )
+    } else {
+        (
+            amount_a_delta(sqrt_price, sqrt_upper, liquidity, round_up),
+            amount_b_delta(sqrt_lower, sqrt_price, liquidity, round_up),
+        )
+    }
+}
+
+/// Returns the amount of A some liquidity holds between two square root prices, i.e.
+/// liquidity * (upper - lower) / (lower * upper), rounded up or down.
+fn amount_a_delta(
+    sqrt_lower: Decimal,
+    sqrt_upper: Decimal,
+    liquidity: Decimal,
+    round_up: bool,
+) -> Decimal {
+    if round_up {
+        div_up(
+            mul_up(liquidity, sqrt_upper - sqrt_lower),
+            sqrt_lower * sqrt_upper,
+        )
+    } else {
+        liquidity * (sqrt_upper - sqrt_lower) / mul_up(sqrt_lower, sqrt_upper)
+    }
+}
+
+/// Returns the amount of B some liquidity holds between two square root prices, i.e.
+/// liquidity * (upper - lower), rounded up or down.
+fn amount_b_delta(
+    sqrt_lower: Decimal,
+    sqrt_upper: Decimal,
+    liquidity: Decimal,
+    round_up: bool,
+) -> Decimal {
+    if round_up {
+        mul_up(liquidity, sqrt_upper - sqrt_lower)
+    } else {
+        liquidity * (sqrt_upper - sqrt_lower)
+    }
+}
@@ -0,0 +1,172 @@
+use scrypto::prelude::*;
+
+/// The maximum number of series terms evaluated before giving up on convergence.
+const MAX_TERMS: usize = 100;
+
+/// The relative error of `pow` is well within this bound, plus one unit of precision, for the bases and exponents
+/// pools use. As in Balancer, `pow_up` and `pow_down` widen results by it to round them in a known direction.
+fn max_pow_relative_error() -> Decimal {
+    dec!("0.00000000000001")
+}
+
+/// Returns ln(2), to the precision of a Decimal.
+fn ln_2() -> Decimal {
+    dec!("0.693147180559945309")
+}
+
+/// Returns the smallest positive Decimal, one unit of its precision.
+pub fn epsilon() -> Decimal {
+    dec!("0.000000000000000001")
+}
+
+/// Returns the product of two non-negative numbers, rounded up to the precision of a Decimal.
+pub fn mul_up(a: Decimal, b: Decimal) -> Decimal {
+    let product = a * b;
+    // The division is exact unless the product was truncated
+    if !b.is_zero() && product / b < a {
+        product + epsilon()
+    } else {
+        product
+    }
+}
+
+/// Returns the quotient of a non-negative number by a positive one, rounded up to the precision of a Decimal.
+pub fn div_up(numerator: Decimal, denominator: Decimal) -> Decimal {
+    let quotient = numerator / denominator;
+    // The product is exact unless the quotient was truncated
+    if quotient * denominator < numerator {
+        quotient + epsilon()
+    } else {
+        quotient
+    }
+}
+
+/// Returns the natural logarithm of a positive number.
+pub fn ln(x: Decimal) -> Decimal {
+    assert!(x > Decimal::zero(), "Logarithm of a non-positive number");
+
+    // Reduce to x = m * 2^k with m in [1, 2)
+    let mut m = x;
+    let mut k: i64 = 0;
+    while m >= dec!("2") {
+        m = m / 2;
+        k += 1;
+    }
+    while m < dec!("1") {
+        m = m * 2;
+        k -= 1;
+    }
+
+    // ln(m) = 2 * atanh(z) = 2 * (z + z^3 / 3 + z^5 / 5 + ...) with z = (m - 1) / (m + 1) <= 1/3
+    let z = (m - 1) / (m + 1);
+    let z_squared = z * z;
+    let mut term = z;
+    let mut sum = Decimal::zero();
+    for n in 0..MAX_TERMS {
+        let addend = term / (2 * n as u64 + 1);
+        if addend.is_zero() {
+            break;
+        }
+        sum += addend;
+        term = term * z_squared;
+    }
+
+    ln_2() * k + sum * 2
+}
+
+/// Returns e raised to the given power.
+pub fn exp(x: Decimal) -> Decimal {
+    // Reduce to x = r + k * ln(2) with r in [0, ln(2))
+    let mut r = x;
+    let mut k: i64 = 0;
+    while r >= ln_2() {
+        r -= ln_2();
+        k += 1;
+    }
+    while r < Decimal::zero() {
+        r += ln_2();
+        k -= 1;
+    }
+
+    // e^r = 1 + r + r^2 / 2! + r^3 / 3! + ...
+    let mut term = Decimal::one();
+    let mut sum = Decimal::one();
+    for n in 1..MAX_TERMS {
+        term = term * r / n as u64;
+        if term.is_zero() {
+            break;
+        }
+        sum += term;
+    }
+
+    // e^x = e^r * 2^k
+    for _ in 0..k.abs() {
+        sum = if k > 0 { sum * 2 } else { sum / 2 };
+    }
+    sum
+}
+
+/// Returns a positive base raised to any power.
+pub fn pow(base: Decimal, exponent: Decimal) -> Decimal {
+    if exponent.is_zero() || base == Decimal::one() {
+        return Decimal::one();
+    }
+    exp(exponent * ln(base))
+}
+
+/// Returns a positive base raised to any power, rounded up so that it is at least the exact value.
+pub fn pow_up(base: Decimal, exponent: Decimal) -> Decimal {
+    let result = pow(base, exponent);
+    result + mul_up(result, max_pow_relative_error()) + epsilon()
+}
+
+/// Returns a positive base raised to any power, rounded down so that it is at most the exact value.
+pub fn pow_down(base: Decimal, exponent: Decimal) -> Decimal {
+    let result = pow(base, exponent);
+    let error = mul_up(result, max_pow_relative_error()) + epsilon();
+    if result > error {
+        result - error
+    } else {
+        Decimal::zero()
+    }
+}
+
+/// Returns a base raised to a non-negative integer power, by repeated squaring.
+pub fn powi(base: Decimal, exponent: u32) -> Decimal {
+    let mut result = Decimal::one();
+    let mut base = base;
+    let mut exponent = exponent;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result * base;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = base * base;
+        }
+    }
+    result
+}
+
+/// Returns the square root of a non-negative number.
+pub fn sqrt(x: Decimal) -> Decimal {
+    assert!(x >= Decimal::zero(), "Square root of a negative number");
+    if x.is_zero() {
+        return Decimal::zero();
+    }
+
+    // Newton's method, starting above the root so that it converges from above
+    let mut y = if x > Decimal::one() {
+        x
+    } else {
+        Decimal::one()
+    };
+    for _ in 0..MAX_TERMS {
+        let next_y = (y + x / y) / 2;
+        if next_y >= y {
+            break;
+        }
+        y = next_y;
+    }
+    y
+}
@@ -0,0 +1,740 @@
+use crate::math::{div_up, mul_up};
+use scrypto::prelude::*;
+
+/// The number of price observations kept for TWAP lookups.
+const OBSERVATION_CAPACITY: usize = 64;
+
+/// The TWAP window used by `get_price` until the owner sets another one.
+const DEFAULT_TWAP_WINDOW: u64 = 10;
+
+#[derive(NonFungibleData, ScryptoSbor)]
+pub struct FlashSwapDue {
+    /// The amount of token A lent out
+    pub amount_out_a: Decimal,
+    /// The amount of token B lent out
+    pub amount_out_b: Decimal,
+    /// The reserve of token A before the flash swap
+    pub reserve_a: Decimal,
+    /// The reserve of token B before the flash swap
+    pub reserve_b: Decimal,
+}
+
+#[blueprint]
+mod radiswap {
+    struct Radiswap {
+        /// The resource address of LP token.
+        lp_resource_address: ResourceAddress,
+        /// LP tokens mint badge.
+        lp_mint_badge: Vault,
+        /// The reserve for token A.
+        a_pool: Vault,
+        /// The reserve for token B.
+        b_pool: Vault,
+        /// The fee to apply for every swap
+        fee: Decimal,
+        /// The standard (Uniswap-like) DEX follows the X*Y=K rule. Since we enable a user defined 'lp_initial_supply', we need to store this value to recover incase all liquidity is removed from the system.
+        /// Adding and removing liquidity does not change this ratio, this ratio is only changed upon swaps.
+        lp_per_asset_ratio: Decimal,
+        /// The owner badge resource address
+        owner_badge: ResourceAddress,
+        /// The share of every swap fee which goes to the protocol instead of the LPs
+        protocol_fee_share: Decimal,
+        /// The protocol fees accrued in token A.
+        protocol_fees_a: Vault,
+        /// The protocol fees accrued in token B.
+        protocol_fees_b: Vault,
+        /// The sum of every price of A (in B) weighted by the number of epochs it lasted.
+        price_a_cumulative: Decimal,
+        /// The sum of every price of B (in A) weighted by the number of epochs it lasted.
+        price_b_cumulative: Decimal,
+        /// The epoch the cumulative prices were last updated.
+        last_observed_epoch: u64,
+        /// Ring buffer of past cumulative prices, at most one per epoch.
+        observations: Vec<Observation>,
+        /// The position in `observations` the next observation is written to.
+        observation_index: usize,
+        /// The window in epochs of the TWAP returned by `get_price`.
+        twap_window: u64,
+        /// The resource address of the transient flash swap receipts.
+        flash_swap_resource_address: ResourceAddress,
+        /// Whether a flash swap is outstanding, during which the reserves may not be touched.
+        locked: bool,
+    }
+
+    impl Radiswap {
+        /// Creates a Radiswap component for token pair A/B and returns the component address
+        /// along with the initial LP tokens and an owner badge for managing the protocol fee.
+        pub fn instantiate_pool(
+            a_tokens: Bucket,
+            b_tokens: Bucket,
+            lp_initial_supply: Decimal,
+            lp_symbol: String,
+            lp_name: String,
+            lp_url: String,
+            fee: Decimal,
+        ) -> (ComponentAddress, Bucket, Bucket) {
+            // Check arguments
+            assert!(
+                !a_tokens.is_empty() && !b_tokens.is_empty(),
+                "You must pass in an initial supply of each token"
+            );
+            assert!(
+                fee >= dec!("0") && fee < dec!("1"),
+                "Invalid fee in thousandths"
+            );
+
+            // Instantiate our LP token and mint an initial supply of them
+            let lp_mint_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "LP Token Mint Auth")
+                .mint_initial_supply(1);
+            let lp_resource_address = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_MAXIMUM)
+                .metadata("symbol", lp_symbol)
+                .metadata("name", lp_name)
+                .metadata("url", lp_url)
+                .mintable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                .burnable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                .create_with_no_initial_supply();
+
+            // Define a "transient" flash swap receipt which can never be deposited, only burned on settlement
+            let flash_swap_resource_address =
+                ResourceBuilder::new_uuid_non_fungible::<FlashSwapDue>()
+                    .metadata(
+                        "name",
+                        "Promise token for Radiswap - must be returned to be burned!",
+                    )
+                    .mintable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                    .burnable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                    .restrict_deposit(rule!(deny_all), LOCKED)
+                    .create_with_no_initial_supply();
+
+            let lp_tokens = lp_mint_badge.authorize(|| {
+                borrow_resource_manager!(lp_resource_address).mint(lp_initial_supply)
+            });
+
+            // ratio = initial supply / (x * y) = initial supply / k
+            let lp_per_asset_ratio = lp_initial_supply / (a_tokens.amount() * b_tokens.amount());
+
+            // Only the owner may switch on the protocol fee and withdraw what it accrues
+            let owner_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "Radiswap Owner Badge")
+                .mint_initial_supply(1);
+            let rules = AccessRulesConfig::new()
+                .method(
+                    "set_protocol_fee_share",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "withdraw_protocol_fees",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "set_twap_window",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .default(rule!(allow_all), AccessRule::DenyAll);
+
+            // Instantiate our Radiswap component
+            let component = Self {
+                lp_resource_address,
+                lp_mint_badge: Vault::with_bucket(lp_mint_badge),
+                protocol_fees_a: Vault::new(a_tokens.resource_address()),
+                protocol_fees_b: Vault::new(b_tokens.resource_address()),
+                a_pool: Vault::with_bucket(a_tokens),
+                b_pool: Vault::with_bucket(b_tokens),
+                fee,
+                lp_per_asset_ratio,
+                owner_badge: owner_badge.resource_address(),
+                protocol_fee_share: Decimal::zero(),
+                price_a_cumulative: Decimal::zero(),
+                price_b_cumulative: Decimal::zero(),
+                last_observed_epoch: Runtime::current_epoch(),
+                observations: vec![Observation {
+                    epoch: Runtime::current_epoch(),
+                    price_a_cumulative: Decimal::zero(),
+                    price_b_cumulative: Decimal::zero(),
+                }],
+                observation_index: 1,
+                twap_window: DEFAULT_TWAP_WINDOW,
+                flash_swap_resource_address,
+                locked: false,
+            }
+            .instantiate();
+            let radiswap = component.globalize_with_access_rules(rules);
+
+            // Return the new Radiswap component, as well as the initial supply of LP tokens and the owner badge
+            (radiswap, lp_tokens, owner_badge)
+        }
+
+        /// Adds liquidity to this pool and return the LP tokens representing pool shares
+        /// along with any remainder.
+        pub fn add_liquidity(
+            &mut self,
+            mut a_tokens: Bucket,
+            mut b_tokens: Bucket,
+        ) -> (Bucket, Bucket) {
+            self.assert_unlocked();
+            self.update_cumulative_prices();
+
+            // Get the resource manager of the lp tokens
+            let lp_resource_manager = borrow_resource_manager!(self.lp_resource_address);
+
+            // Differentiate LP calculation based on whether pool is empty or not.
+            let (supply_to_mint, remainder) = if lp_resource_manager.total_supply() == 0.into() {
+                // Set initial LP tokens based on previous LP per K ratio.
+                let supply_to_mint =
+                    self.lp_per_asset_ratio * a_tokens.amount() * b_tokens.amount();
+                self.a_pool.put(a_tokens.take(a_tokens.amount()));
+                self.b_pool.put(b_tokens);
+                (supply_to_mint, a_tokens)
+            } else {
+                // The ratio of added liquidity in existing liquidty.
+                let a_ratio = a_tokens.amount() / self.a_pool.amount();
+                let b_ratio = b_tokens.amount() / self.b_pool.amount();
+
+                let (actual_ratio, remainder) = if a_ratio <= b_ratio {
+                    // We will claim all input token A's, and only the correct amount of token B
+                    self.a_pool.put(a_tokens);
+                    self.b_pool
+                        .put(b_tokens.take(self.b_pool.amount() * a_ratio));
+                    (a_ratio, b_tokens)
+                } else {
+                    // We will claim all input token B's, and only the correct amount of token A
+                    self.b_pool.put(b_tokens);
+                    self.a_pool
+                        .put(a_tokens.take(self.a_pool.amount() * b_ratio));
+                    (b_ratio, a_tokens)
+                };
+                (lp_resource_manager.total_supply() * actual_ratio, remainder)
+            };
+
+            // Mint LP tokens according to the share the provider is contributing
+            let lp_tokens = self
+                .lp_mint_badge
+                .authorize(|| lp_resource_manager.mint(supply_to_mint));
+
+            // Return the LP tokens along with any remainder
+            (lp_tokens, remainder)
+        }
+
+        /// Removes liquidity from this pool.
+        pub fn remove_liquidity(&mut self, lp_tokens: Bucket) -> (Bucket, Bucket) {
+            assert!(
+                self.lp_resource_address == lp_tokens.resource_address(),
+                "Wrong token type passed in"
+            );
+
+            self.assert_unlocked();
+            self.update_cumulative_prices();
+
+            // Withdraw the correct amounts of tokens A and B from reserves
+            let (a_amount, b_amount) = self.get_lp_value(lp_tokens.amount());
+            let a_withdrawn = self.a_pool.take(a_amount);
+            let b_withdrawn = self.b_pool.take(b_amount);
+
+            // Burn the LP tokens received
+            self.lp_mint_badge.authorize(|| {
+                lp_tokens.burn();
+            });
+
+            // Return the withdrawn tokens
+            (a_withdrawn, b_withdrawn)
+        }
+
+        /// Swaps token A for B, or vice versa.
+        pub fn swap(&mut self, input_tokens: Bucket) -> Bucket {
+            // Calculate how much of the other token we will return
+            let output_amount =
+                self.get_amount_out(input_tokens.resource_address(), input_tokens.amount());
+
+            self.execute_swap(input_tokens, output_amount)
+        }
+
+        /// Swaps token A for exactly `output_amount` of B, or vice versa, spending at most `max_input`.
+        /// Returns the output tokens along with the unspent input tokens.
+        pub fn swap_exact_output(
+            &mut self,
+            mut input_tokens: Bucket,
+            output_amount: Decimal,
+            max_input: Decimal,
+        ) -> (Bucket, Bucket) {
+            // Calculate how much of the input tokens we need to take
+            let (a, b) = self.get_pair();
+            let output_resource = if input_tokens.resource_address() == a {
+                b
+            } else if input_tokens.resource_address() == b {
+                a
+            } else {
+                panic!(
+                    "Resource {:?} is not part of this pair",
+                    input_tokens.resource_address()
+                );
+            };
+            let input_amount = self.get_amount_in(output_resource, output_amount);
+            assert!(
+                input_amount <= max_input,
+                "Excessive input amount: requires {}, expected at most {}",
+                input_amount,
+                max_input
+            );
+            assert!(
+                input_amount <= input_tokens.amount(),
+                "Insufficient input tokens: requires {}, received {}",
+                input_amount,
+                input_tokens.amount()
+            );
+
+            // Return the tokens owed along with the change
+            let output_tokens = self.execute_swap(input_tokens.take(input_amount), output_amount);
+            (output_tokens, input_tokens)
+        }
+
+        /// Lends out the given amounts of token A and B, along with a receipt which can't be deposited and must be
+        /// passed to `settle_flash_swap` in the same transaction. Until then the pool is locked.
+        pub fn flash_swap(
+            &mut self,
+            amount_out_a: Decimal,
+            amount_out_b: Decimal,
+        ) -> (Bucket, Bucket, Bucket) {
+            self.assert_unlocked();
+            assert!(
+                amount_out_a >= Decimal::zero() && amount_out_b >= Decimal::zero(),
+                "Invalid flash swap amounts"
+            );
+            assert!(
+                amount_out_a > Decimal::zero() || amount_out_b > Decimal::zero(),
+                "Nothing to flash swap"
+            );
+            assert!(
+                amount_out_a < self.a_pool.amount() && amount_out_b < self.b_pool.amount(),
+                "Not enough liquidity to supply this flash swap"
+            );
+
+            self.update_cumulative_prices();
+            self.locked = true;
+
+            // Mint a receipt with the reserves the settlement is checked against
+            let receipt = self.lp_mint_badge.authorize(|| {
+                borrow_resource_manager!(self.flash_swap_resource_address).mint_uuid_non_fungible(
+                    FlashSwapDue {
+                        amount_out_a,
+                        amount_out_b,
+                        reserve_a: self.a_pool.amount(),
+                        reserve_b: self.b_pool.amount(),
+                    },
+                )
+            });
+
+            (
+                self.a_pool.take(amount_out_a),
+                self.b_pool.take(amount_out_b),
+                receipt,
+            )
+        }
+
+        /// Settles a flash swap with whatever token A and B are paid in, and unlocks the pool.
+        /// Fails unless the product of the reserves, net of the fee on the tokens paid in, is at least what it was
+        /// before the flash swap.
+        pub fn settle_flash_swap(
+            &mut self,
+            mut a_tokens: Bucket,
+            mut b_tokens: Bucket,
+            receipt: Bucket,
+        ) {
+            assert!(
+                receipt.resource_address() == self.flash_swap_resource_address,
+                "Incorrect resource passed in for flash swap receipt"
+            );
+            assert!(
+                a_tokens.resource_address() == self.a_pool.resource_address()
+                    && b_tokens.resource_address() == self.b_pool.resource_address(),
+                "Wrong token type passed in"
+            );
+
+            // Check the invariant, charging the fee on the tokens paid in
+            let terms: FlashSwapDue = receipt.non_fungible().data();
+            let a_balance = terms.reserve_a - terms.amount_out_a + a_tokens.amount();
+            let b_balance = terms.reserve_b - terms.amount_out_b + b_tokens.amount();
+            let a_adjusted = a_balance - a_tokens.amount() * self.fee;
+            let b_adjusted = b_balance - b_tokens.amount() * self.fee;
+            assert!(
+                a_adjusted * b_adjusted >= terms.reserve_a * terms.reserve_b,
+                "Insufficient repayment given for your flash swap!"
+            );
+
+            // Put the protocol's cut of the fee aside and the rest of the tokens into our pool
+            let lp_resource_manager = borrow_resource_manager!(self.lp_resource_address);
+            self.protocol_fees_a
+                .put(a_tokens.take(a_tokens.amount() * self.fee * self.protocol_fee_share));
+            self.protocol_fees_b
+                .put(b_tokens.take(b_tokens.amount() * self.fee * self.protocol_fee_share));
+            self.a_pool.put(a_tokens);
+            self.b_pool.put(b_tokens);
+            self.lp_per_asset_ratio =
+                lp_resource_manager.total_supply() / (self.a_pool.amount() * self.b_pool.amount());
+
+            // We have our payment; we can now burn the transient token and unlock the pool
+            self.lp_mint_badge.authorize(|| receipt.burn());
+            self.locked = false;
+        }
+
+        /// Returns the resource address of the transient flash swap receipts.
+        pub fn flash_swap_resource_address(&self) -> ResourceAddress {
+            self.flash_swap_resource_address
+        }
+
+        /// Adds liquidity like `add_liquidity`, failing if fewer than `min_lp_tokens` are minted
+        /// or if the deadline epoch has passed.
+        pub fn add_liquidity_with_limits(
+            &mut self,
+            a_tokens: Bucket,
+            b_tokens: Bucket,
+            min_lp_tokens: Decimal,
+            deadline: u64,
+        ) -> (Bucket, Bucket) {
+            Self::assert_deadline(deadline);
+
+            let (lp_tokens, remainder) = self.add_liquidity(a_tokens, b_tokens);
+            assert!(
+                lp_tokens.amount() >= min_lp_tokens,
+                "Insufficient LP tokens minted: received {}, expected at least {}",
+                lp_tokens.amount(),
+                min_lp_tokens
+            );
+
+            (lp_tokens, remainder)
+        }
+
+        /// Removes liquidity like `remove_liquidity`, failing if less than `min_a_amount` of token A
+        /// or `min_b_amount` of token B is withdrawn, or if the deadline epoch has passed.
+        pub fn remove_liquidity_with_limits(
+            &mut self,
+            lp_tokens: Bucket,
+            min_a_amount: Decimal,
+            min_b_amount: Decimal,
+            deadline: u64,
+        ) -> (Bucket, Bucket) {
+            Self::assert_deadline(deadline);
+
+            let (a_withdrawn, b_withdrawn) = self.remove_liquidity(lp_tokens);
+            assert!(
+                a_withdrawn.amount() >= min_a_amount,
+                "Insufficient token A withdrawn: received {}, expected at least {}",
+                a_withdrawn.amount(),
+                min_a_amount
+            );
+            assert!(
+                b_withdrawn.amount() >= min_b_amount,
+                "Insufficient token B withdrawn: received {}, expected at least {}",
+                b_withdrawn.amount(),
+                min_b_amount
+            );
+
+            (a_withdrawn, b_withdrawn)
+        }
+
+        /// Swaps like `swap`, failing if less than `min_output` is returned
+        /// or if the deadline epoch has passed.
+        pub fn swap_with_limits(
+            &mut self,
+            input_tokens: Bucket,
+            min_output: Decimal,
+            deadline: u64,
+        ) -> Bucket {
+            Self::assert_deadline(deadline);
+
+            let output_tokens = self.swap(input_tokens);
+            assert!(
+                output_tokens.amount() >= min_output,
+                "Insufficient output amount: received {}, expected at least {}",
+                output_tokens.amount(),
+                min_output
+            );
+
+            output_tokens
+        }
+
+        /// Returns the resource addresses of the pair.
+        pub fn get_pair(&self) -> (ResourceAddress, ResourceAddress) {
+            (
+                self.a_pool.resource_address(),
+                self.b_pool.resource_address(),
+            )
+        }
+
+        /// Returns the reserves of token A and B.
+        pub fn get_reserves(&self) -> (Decimal, Decimal) {
+            (self.a_pool.amount(), self.b_pool.amount())
+        }
+
+        /// Returns the marginal price of BASE, expressed in the other token of the pair,
+        /// or `None` if the pool is empty.
+        pub fn get_spot_price(&self, base: ResourceAddress) -> Option<Decimal> {
+            let (base_reserve, quote_reserve) = self.get_directed_reserves(base);
+            if base_reserve.is_zero() || quote_reserve.is_zero() {
+                return None;
+            }
+            Some(quote_reserve / base_reserve)
+        }
+
+        /// Returns how much of the other token `swap` would return for the given input.
+        pub fn get_amount_out(
+            &self,
+            input_resource: ResourceAddress,
+            input_amount: Decimal,
+        ) -> Decimal {
+            let (input_reserve, output_reserve) = self.get_directed_reserves(input_resource);
+            calculate_amount_out(input_amount, input_reserve, output_reserve, self.fee)
+        }
+
+        /// Returns how much of the other token `swap` would need as input to return the given output.
+        pub fn get_amount_in(
+            &self,
+            output_resource: ResourceAddress,
+            output_amount: Decimal,
+        ) -> Decimal {
+            let (output_reserve, input_reserve) = self.get_directed_reserves(output_resource);
+            calculate_amount_in(output_amount, input_reserve, output_reserve, self.fee)
+        }
+
+        /// Returns the amounts of token A and B that `remove_liquidity` would return for the given LP amount.
+        pub fn get_lp_value(&self, lp_amount: Decimal) -> (Decimal, Decimal) {
+            // Calculate the share based on the LP amount
+            let share =
+                lp_amount / borrow_resource_manager!(self.lp_resource_address).total_supply();
+
+            (self.a_pool.amount() * share, self.b_pool.amount() * share)
+        }
+
+        /// Sets the share of every swap fee which goes to the protocol, from 0 (off) to 1.
+        pub fn set_protocol_fee_share(&mut self, protocol_fee_share: Decimal) {
+            assert!(
+                protocol_fee_share >= dec!("0") && protocol_fee_share <= dec!("1"),
+                "Invalid protocol fee share"
+            );
+            self.protocol_fee_share = protocol_fee_share;
+        }
+
+        /// Withdraws all protocol fees accrued in token A and B.
+        pub fn withdraw_protocol_fees(&mut self) -> (Bucket, Bucket) {
+            (
+                self.protocol_fees_a.take_all(),
+                self.protocol_fees_b.take_all(),
+            )
+        }
+
+        /// Returns the share of every swap fee which goes to the protocol.
+        pub fn get_protocol_fee_share(&self) -> Decimal {
+            self.protocol_fee_share
+        }
+
+        /// Returns the protocol fees accrued in token A and B.
+        pub fn get_protocol_fees(&self) -> (Decimal, Decimal) {
+            (self.protocol_fees_a.amount(), self.protocol_fees_b.amount())
+        }
+
+        /// Returns the owner badge resource address.
+        pub fn owner_badge_address(&self) -> ResourceAddress {
+            self.owner_badge
+        }
+
+        /// Returns the time-weighted average price of BASE in QUOTE over at least the last `window_epochs`,
+        /// or `None` if the pool is not for this pair or has no observation old enough.
+        pub fn consult(
+            &self,
+            base: ResourceAddress,
+            quote: ResourceAddress,
+            window_epochs: u64,
+        ) -> Option<Decimal> {
+            let (a, b) = self.get_pair();
+            if !((base == a && quote == b) || (base == b && quote == a)) || window_epochs == 0 {
+                return None;
+            }
+
+            // Find the newest observation made at least `window_epochs` ago
+            let current_epoch = Runtime::current_epoch();
+            let target_epoch = current_epoch.saturating_sub(window_epochs);
+            let observation = self
+                .observations
+                .iter()
+                .filter(|observation| observation.epoch <= target_epoch)
+                .max_by_key(|observation| observation.epoch)?;
+
+            // Average the cumulative prices between that observation and now
+            let elapsed = current_epoch - observation.epoch;
+            if elapsed == 0 {
+                return None;
+            }
+            let (price_a_cumulative, price_b_cumulative) = self.get_cumulative_prices();
+            if base == a {
+                Some((price_a_cumulative - observation.price_a_cumulative) / elapsed)
+            } else {
+                Some((price_b_cumulative - observation.price_b_cumulative) / elapsed)
+            }
+        }
+
+        /// Returns the time-weighted average price of BASE in QUOTE over the configured TWAP window,
+        /// so that this pool can be used wherever a `PriceOracle` is expected.
+        pub fn get_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Option<Decimal> {
+            self.consult(base, quote, self.twap_window)
+        }
+
+        /// Returns the cumulative prices of A and B as of the current epoch.
+        pub fn get_cumulative_prices(&self) -> (Decimal, Decimal) {
+            let elapsed = Runtime::current_epoch() - self.last_observed_epoch;
+            if elapsed == 0 || self.a_pool.amount().is_zero() || self.b_pool.amount().is_zero() {
+                return (self.price_a_cumulative, self.price_b_cumulative);
+            }
+
+            (
+                self.price_a_cumulative + self.b_pool.amount() / self.a_pool.amount() * elapsed,
+                self.price_b_cumulative + self.a_pool.amount() / self.b_pool.amount() * elapsed,
+            )
+        }
+
+        /// Sets the window in epochs of the TWAP returned by `get_price`.
+        pub fn set_twap_window(&mut self, twap_window: u64) {
+            assert!(twap_window > 0, "Invalid TWAP window");
+            self.twap_window = twap_window;
+        }
+
+        /// Returns the window in epochs of the TWAP returned by `get_price`.
+        pub fn get_twap_window(&self) -> u64 {
+            self.twap_window
+        }
+
+        /// Accumulates the prices which held since the last update and records an observation.
+        /// Must be called before the reserves change.
+        fn update_cumulative_prices(&mut self) {
+            let current_epoch = Runtime::current_epoch();
+            if current_epoch == self.last_observed_epoch {
+                return;
+            }
+
+            let (price_a_cumulative, price_b_cumulative) = self.get_cumulative_prices();
+            self.price_a_cumulative = price_a_cumulative;
+            self.price_b_cumulative = price_b_cumulative;
+            self.last_observed_epoch = current_epoch;
+
+            let observation = Observation {
+                epoch: current_epoch,
+                price_a_cumulative,
+                price_b_cumulative,
+            };
+            if self.observations.len() < OBSERVATION_CAPACITY {
+                self.observations.push(observation);
+            } else {
+                self.observations[self.observation_index] = observation;
+            }
+            self.observation_index = (self.observation_index + 1) % OBSERVATION_CAPACITY;
+        }
+
+        /// Returns the reserves as (reserve of RESOURCE, reserve of the other token).
+        fn get_directed_reserves(&self, resource: ResourceAddress) -> (Decimal, Decimal) {
+            if resource == self.a_pool.resource_address() {
+                (self.a_pool.amount(), self.b_pool.amount())
+            } else if resource == self.b_pool.resource_address() {
+                (self.b_pool.amount(), self.a_pool.amount())
+            } else {
+                panic!("Resource {:?} is not part of this pair", resource);
+            }
+        }
+
+        /// Puts the input tokens into their pool and takes the output amount from the other one.
+        fn execute_swap(&mut self, mut input_tokens: Bucket, output_amount: Decimal) -> Bucket {
+            self.assert_unlocked();
+            self.update_cumulative_prices();
+
+            // Get the resource manager of the lp tokens
+            let lp_resource_manager = borrow_resource_manager!(self.lp_resource_address);
+
+            // The protocol's cut of the swap fee does not stay in the pool
+            let protocol_fee =
+                input_tokens.take(input_tokens.amount() * self.fee * self.protocol_fee_share);
+
+            let output_tokens = if input_tokens.resource_address() == self.a_pool.resource_address()
+            {
+                // Put the protocol fee aside and the rest of the input tokens into our pool
+                self.protocol_fees_a.put(protocol_fee);
+                self.a_pool.put(input_tokens);
+
+                // Return the tokens owed
+                self.b_pool.take(output_amount)
+            } else {
+                // Put the protocol fee aside and the rest of the input tokens into our pool
+                self.protocol_fees_b.put(protocol_fee);
+                self.b_pool.put(input_tokens);
+
+                // Return the tokens owed
+                self.a_pool.take(output_amount)
+            };
+
+            // Accrued fees change the raio
+            self.lp_per_asset_ratio =
+                lp_resource_manager.total_supply() / (self.a_pool.amount() * self.b_pool.amount());
+
+            output_tokens
+        }
+
+        /// Fails if a flash swap is outstanding.
+        fn assert_unlocked(&self) {
+            assert!(!self.locked, "Pool is locked by an outstanding flash swap");
+        }
+
+        /// Fails if the current epoch is past the deadline epoch.
+        fn assert_deadline(deadline: u64) {
+            assert!(
+                Runtime::current_epoch() <= deadline,
+                "Deadline epoch {} has passed",
+                deadline
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, ScryptoSbor)]
+pub struct Observation {
+    /// The epoch of the observation
+    pub epoch: u64,
+    /// The cumulative price of A at that epoch
+    pub price_a_cumulative: Decimal,
+    /// The cumulative price of B at that epoch
+    pub price_b_cumulative: Decimal,
+}
+
+/// Returns the output of a constant-product swap, charging the fee on the input.
+fn calculate_amount_out(
+    input_amount: Decimal,
+    input_reserve: Decimal,
+    output_reserve: Decimal,
+    fee: Decimal,
+) -> Decimal {
+    let fee_amount = input_amount * fee;
+    output_reserve - input_reserve * output_reserve / (input_amount - fee_amount + input_reserve)
+}
+
+/// Returns the input of a constant-product swap needed for the given output, charging the fee on the input.  The
+/// input is rounded up, so that rounding never lets the constant product fall.
+fn calculate_amount_in(
+    output_amount: Decimal,
+    input_reserve: Decimal,
+    output_reserve: Decimal,
+    fee: Decimal,
+) -> Decimal {
+    assert!(
+        output_amount < output_reserve,
+        "Not enough liquidity to return {}",
+        output_amount
+    );
+    // x * y = (x + dx) * (y - dy) gives dx = x * dy / (y - dy)
+    let input_after_fee = div_up(
+        mul_up(input_reserve, output_amount),
+        output_reserve - output_amount,
+    );
+    div_up(input_after_fee, dec!("1") - fee)
+}
@@ -0,0 +1,226 @@
+use scrypto::prelude::*;
+
+external_component! {
+    RadiswapComponentTarget {
+        fn add_liquidity(&mut self, a_tokens: Bucket, b_tokens: Bucket) -> (Bucket, Bucket);
+        fn remove_liquidity(&mut self, lp_tokens: Bucket) -> (Bucket, Bucket);
+        fn swap(&mut self, input_tokens: Bucket) -> Bucket;
+        fn get_pair(&self) -> (ResourceAddress, ResourceAddress);
+        fn get_amount_out(&self, input_resource: ResourceAddress, input_amount: Decimal) -> Decimal;
+    }
+}
+
+/// The maximum number of pools a single routed swap may go through.
+const MAX_HOPS: usize = 4;
+
+#[blueprint]
+mod radiswap_router {
+    struct RadiswapRouter {
+        /// The registered pools, keyed by the pair returned from `get_pair()`.
+        pools: HashMap<(ResourceAddress, ResourceAddress), RadiswapComponentTarget>,
+        /// The admin badge resource address
+        admin_badge: ResourceAddress,
+    }
+
+    impl RadiswapRouter {
+        /// Creates a RadiswapRouter component and returns the component address
+        /// along with an admin badge for managing the registered pools.
+        pub fn instantiate_router() -> (ComponentAddress, Bucket) {
+            let admin_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "Radiswap Router Admin Badge")
+                .mint_initial_supply(1);
+
+            let rules = AccessRulesConfig::new()
+                .method(
+                    "register_pool",
+                    rule!(require(admin_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "deregister_pool",
+                    rule!(require(admin_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .default(rule!(allow_all), AccessRule::DenyAll);
+
+            let component = Self {
+                pools: HashMap::new(),
+                admin_badge: admin_badge.resource_address(),
+            }
+            .instantiate();
+            let component_address = component.globalize_with_access_rules(rules);
+
+            (component_address, admin_badge)
+        }
+
+        /// Adds a Radiswap pool to the registry.
+        pub fn register_pool(&mut self, pool_address: ComponentAddress) {
+            let pool: RadiswapComponentTarget = pool_address.into();
+            let (a, b) = pool.get_pair();
+            assert!(
+                !self.pools.contains_key(&(a, b)) && !self.pools.contains_key(&(b, a)),
+                "A pool for this pair is already registered"
+            );
+
+            self.pools.insert((a, b), pool);
+        }
+
+        /// Removes the pool of pair A/B from the registry.
+        pub fn deregister_pool(&mut self, a: ResourceAddress, b: ResourceAddress) {
+            let removed = self
+                .pools
+                .remove(&(a, b))
+                .or_else(|| self.pools.remove(&(b, a)));
+            assert!(removed.is_some(), "No pool registered for this pair");
+        }
+
+        /// Returns the pairs of all registered pools.
+        pub fn get_pairs(&self) -> Vec<(ResourceAddress, ResourceAddress)> {
+            self.pools.keys().cloned().collect()
+        }
+
+        /// Returns the shortest path of resources leading from INPUT to OUTPUT, if any.
+        pub fn find_path(
+            &self,
+            input: ResourceAddress,
+            output: ResourceAddress,
+        ) -> Option<Vec<ResourceAddress>> {
+            if input == output {
+                return None;
+            }
+
+            // Breadth-first search over the pool graph, remembering where each resource was reached from
+            let mut reached_from: HashMap<ResourceAddress, ResourceAddress> = HashMap::new();
+            let mut frontier = vec![input];
+            for _ in 0..MAX_HOPS {
+                let mut next_frontier = Vec::new();
+                for resource in frontier {
+                    for (a, b) in self.pools.keys() {
+                        let neighbour = if *a == resource {
+                            *b
+                        } else if *b == resource {
+                            *a
+                        } else {
+                            continue;
+                        };
+                        if neighbour == input || reached_from.contains_key(&neighbour) {
+                            continue;
+                        }
+                        reached_from.insert(neighbour, resource);
+
+                        if neighbour == output {
+                            let mut path = vec![output];
+                            let mut current = output;
+                            while current != input {
+                                current = *reached_from.get(&current).unwrap();
+                                path.push(current);
+                            }
+                            path.reverse();
+                            return Some(path);
+                        }
+                        next_frontier.push(neighbour);
+                    }
+                }
+                frontier = next_frontier;
+            }
+
+            None
+        }
+
+        /// Returns the amounts received at each step of the path for the given input amount,
+        /// the first being the input amount itself.
+        pub fn get_amounts_out(
+            &self,
+            input_amount: Decimal,
+            path: Vec<ResourceAddress>,
+        ) -> Vec<Decimal> {
+            assert!(path.len() >= 2, "Path must contain at least 1 hop");
+
+            let mut amounts = vec![input_amount];
+            for hop in path.windows(2) {
+                let pool = self.pools.get(&self.get_pool_key(hop[0], hop[1])).unwrap();
+                amounts.push(pool.get_amount_out(hop[0], *amounts.last().unwrap()));
+            }
+            amounts
+        }
+
+        /// Swaps the input tokens for OUTPUT through the shortest path of registered pools,
+        /// failing if less than `min_output` is received.
+        pub fn swap(
+            &mut self,
+            input_tokens: Bucket,
+            output: ResourceAddress,
+            min_output: Decimal,
+        ) -> Bucket {
+            let path = self
+                .find_path(input_tokens.resource_address(), output)
+                .expect("No path found between the input and output resources");
+
+            self.swap_along_path(input_tokens, path, min_output)
+        }
+
+        /// Swaps the input tokens hop by hop along the given path of resources,
+        /// failing if less than `min_output` of the last resource is received.
+        pub fn swap_along_path(
+            &mut self,
+            input_tokens: Bucket,
+            path: Vec<ResourceAddress>,
+            min_output: Decimal,
+        ) -> Bucket {
+            assert!(
+                path.len() >= 2 && path.len() <= MAX_HOPS + 1,
+                "Path must contain between 1 and {} hops",
+                MAX_HOPS
+            );
+            assert!(
+                input_tokens.resource_address() == path[0],
+                "Input tokens do not match the start of the path"
+            );
+
+            let mut tokens = input_tokens;
+            for hop in path.windows(2) {
+                tokens = self.get_pool(hop[0], hop[1]).swap(tokens);
+            }
+
+            assert!(
+                tokens.amount() >= min_output,
+                "Insufficient output amount: received {}, expected at least {}",
+                tokens.amount(),
+                min_output
+            );
+
+            tokens
+        }
+
+        /// Returns the admin badge resource address.
+        pub fn admin_badge_address(&self) -> ResourceAddress {
+            self.admin_badge
+        }
+
+        /// Returns the registered pool of pair A/B, in either order.
+        fn get_pool(
+            &mut self,
+            a: ResourceAddress,
+            b: ResourceAddress,
+        ) -> &mut RadiswapComponentTarget {
+            let key = self.get_pool_key(a, b);
+            self.pools.get_mut(&key).unwrap()
+        }
+
+        /// Returns the registry key of the pool of pair A/B.
+        fn get_pool_key(
+            &self,
+            a: ResourceAddress,
+            b: ResourceAddress,
+        ) -> (ResourceAddress, ResourceAddress) {
+            if self.pools.contains_key(&(a, b)) {
+                (a, b)
+            } else if self.pools.contains_key(&(b, a)) {
+                (b, a)
+            } else {
+                panic!("No pool registered for pair {:?}/{:?}", a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use crate::math::epsilon;
+use scrypto::prelude::*;
+
+/// The highest amplification coefficient a pool may be ramped to.
+const MAX_AMPLIFICATION: u64 = 1_000_000;
+
+/// The most the amplification coefficient may be multiplied or divided by in a single ramp.
+const MAX_AMPLIFICATION_CHANGE: u64 = 10;
+
+/// The fewest epochs a ramp of the amplification coefficient may take.
+const MIN_RAMP_EPOCHS: u64 = 10;
+
+/// The maximum number of Newton iterations when solving the invariant.
+const MAX_ITERATIONS: usize = 255;
+
+#[blueprint]
+mod stable_swap {
+    /// A Curve-style StableSwap pool for pegged assets. It exposes the same surface as Radiswap, so routers and
+    /// other blueprints can use either kind of pool interchangeably.
+    struct StableSwap {
+        /// The resource address of LP token.
+        lp_resource_address: ResourceAddress,
+        /// LP tokens mint badge.
+        lp_mint_badge: Vault,
+        /// The reserve for token A.
+        a_pool: Vault,
+        /// The reserve for token B.
+        b_pool: Vault,
+        /// The fee to apply for every swap
+        fee: Decimal,
+        /// The owner badge resource address
+        owner_badge: ResourceAddress,
+        /// The amplification coefficient at the start of the current ramp.
+        initial_amplification: Decimal,
+        /// The amplification coefficient at the end of the current ramp.
+        future_amplification: Decimal,
+        /// The epoch the current ramp started.
+        ramp_start_epoch: u64,
+        /// The epoch the current ramp ends.
+        ramp_end_epoch: u64,
+    }
+
+    impl StableSwap {
+        /// Creates a StableSwap component for token pair A/B and returns the component address
+        /// along with the initial LP tokens and an owner badge for ramping the amplification coefficient.
+        pub fn instantiate_pool(
+            a_tokens: Bucket,
+            b_tokens: Bucket,
+            lp_symbol: String,
+            lp_name: String,
+            lp_url: String,
+            fee: Decimal,
+            amplification: Decimal,
+        ) -> (ComponentAddress, Bucket, Bucket) {
+            // Check arguments
+            assert!(
+                !a_tokens.is_empty() && !b_tokens.is_empty(),
+                "You must pass in an initial supply of each token"
+            );
+            assert!(
+                fee >= dec!("0") && fee <= dec!("1"),
+                "Invalid fee in thousandths"
+            );
+            assert!(
+                amplification > dec!("0") && amplification <= Decimal::from(MAX_AMPLIFICATION),
+                "Invalid amplification coefficient"
+            );
+
+            // Instantiate our LP token and mint one LP token per unit of the initial invariant
+            let lp_mint_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "LP Token Mint Auth")
+                .mint_initial_supply(1);
+            let lp_resource_address = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_MAXIMUM)
+                .metadata("symbol", lp_symbol)
+                .metadata("name", lp_name)
+                .metadata("url", lp_url)
+                .mintable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                .burnable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                .create_with_no_initial_supply();
+
+            let d = calculate_d(a_tokens.amount(), b_tokens.amount(), amplification);
+            let lp_tokens =
+                lp_mint_badge.authorize(|| borrow_resource_manager!(lp_resource_address).mint(d));
+
+            // Only the owner may ramp the amplification coefficient
+            let owner_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "StableSwap Owner Badge")
+                .mint_initial_supply(1);
+            let rules = AccessRulesConfig::new()
+                .method(
+                    "ramp_amplification",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .method(
+                    "stop_ramp_amplification",
+                    rule!(require(owner_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .default(rule!(allow_all), AccessRule::DenyAll);
+
+            // Instantiate our StableSwap component
+            let component = Self {
+                lp_resource_address,
+                lp_mint_badge: Vault::with_bucket(lp_mint_badge),
+                a_pool: Vault::with_bucket(a_tokens),
+                b_pool: Vault::with_bucket(b_tokens),
+                fee,
+                owner_badge: owner_badge.resource_address(),
+                initial_amplification: amplification,
+                future_amplification: amplification,
+                ramp_start_epoch: Runtime::current_epoch(),
+                ramp_end_epoch: Runtime::current_epoch(),
+            }
+            .instantiate();
+            let stable_swap = component.globalize_with_access_rules(rules);
+
+            // Return the new StableSwap component, as well as the initial supply of LP tokens and the owner badge
+            (stable_swap, lp_tokens, owner_badge)
+        }
+
+        /// Adds liquidity to this pool and return the LP tokens representing pool shares
+        /// along with any remainder.
+        pub fn add_liquidity(
+            &mut self,
+            mut a_tokens: Bucket,
+            mut b_tokens: Bucket,
+        ) -> (Bucket, Bucket) {
+            // Get the resource manager of the lp tokens
+            let lp_resource_manager = borrow_resource_manager!(self.lp_resource_address);
+
+            // Differentiate LP calculation based on whether pool is empty or not.
+            let (supply_to_mint, remainder) = if lp_resource_manager.total_supply() == 0.into() {
+                // Mint one LP token per unit of the new invariant
+                let supply_to_mint = calculate_d(
+                    a_tokens.amount(),
+                    b_tokens.amount(),
+                    self.get_amplification(),
+                );
+                self.a_pool.put(a_tokens.take(a_tokens.amount()));
+                self.b_pool.put(b_tokens);
+                (supply_to_mint, a_tokens)
+            } else {
+                // The ratio of added liquidity in existing liquidty.
+                let a_ratio = a_tokens.amount() / self.a_pool.amount();
+                let b_ratio = b_tokens.amount() / self.b_pool.amount();
+
+                let (actual_ratio, remainder) = if a_ratio <= b_ratio {
+                    // We will claim all input token A's, and only the correct amount of token B
+                    self.a_pool.put(a_tokens);
+                    self.b_pool
+                        .put(b_tokens.take(self.b_pool.amount() * a_ratio));
+                    (a_ratio, b_tokens)
+                } else {
+                    // We will claim all input token B's, and only the correct amount of token A
+                    self.b_pool.put(b_tokens);
+                    self.a_pool
+                        .put(a_tokens.take(self.a_pool.amount() * b_ratio));
+                    (b_ratio, a_tokens)
+                };
+                (lp_resource_manager.total_supply() * actual_ratio, remainder)
+            };
+
+            // Mint LP tokens according to the share the provider is contributing
+            let lp_tokens = self
+                .lp_mint_badge
+                .authorize(|| lp_resource_manager.mint(supply_to_mint));
+
+            // Return the LP tokens along with any remainder
+            (lp_tokens, remainder)
+        }
+
+        /// Removes liquidity from this pool.
+        pub fn remove_liquidity(&mut self, lp_tokens: Bucket) -> (Bucket, Bucket) {
+            assert!(
+                self.lp_resource_address == lp_tokens.resource_address(),
+                "Wrong token type passed in"
+            );
+
+            // Withdraw the correct amounts of tokens A and B from reserves
+            let (a_amount, b_amount) = self.get_lp_value(lp_tokens.amount());
+            let a_withdrawn = self.a_pool.take(a_amount);
+            let b_withdrawn = self.b_pool.take(b_amount);
+
+            // Burn the LP tokens received
+            self.lp_mint_badge.authorize(|| {
+                lp_tokens.burn();
+            });
+
+            // Return the withdrawn tokens
+            (a_withdrawn, b_withdrawn)
+        }
+
+        /// Swaps token A for B, or vice versa.
+        pub fn swap(&mut self, input_tokens: Bucket) -> Bucket {
+            // Calculate how much of the other token we will return
+            let output_amount =
+                self.get_amount_out(input_tokens.resource_address(), input_tokens.amount());
+
+            if input_tokens.resource_address() == self.a_pool.resource_address() {
+                self.a_pool.put(input_tokens);
+                self.b_pool.take(output_amount)
+            } else {
+                self.b_pool.put(input_tokens);
+                self.a_pool.take(output_amount)
+            }
+        }
+
+        /// Returns the resource addresses of the pair.
+        pub fn get_pair(&self) -> (ResourceAddress, ResourceAddress) {
+            (
+                self.a_pool.resource_address(),
+                self.b_pool.resource_address(),
+            )
+        }
+
+        /// Returns the reserves of token A and B.
+        pub fn get_reserves(&self) -> (Decimal, Decimal) {
+            (self.a_pool.amount(), self.b_pool.amount())
+        }
+
+        /// Returns how much of the other token `swap` would return for the given input.
+        pub fn get_amount_out(
+            &self,
+            input_resource: ResourceAddress,
+            input_amount: Decimal,
+        ) -> Decimal {
+            let (input_reserve, output_reserve) =
+                if input_resource == self.a_pool.resource_address() {
+                    (self.a_pool.amount(), self.b_pool.amount())
+                } else if input_resource == self.b_pool.resource_address() {
+                    (self.b_pool.amount(), self.a_pool.amount())
+                } else {
+                    panic!("Resource {:?} is not part of this pair", input_resource);
+                };
+
+            // Keep the invariant constant while the input, less the fee, enters the pool
+            let amplification = self.get_amplification();
+            let d = calculate_d(input_reserve, output_reserve, amplification);
+            let new_output_reserve = calculate_y(
+                input_reserve + input_amount * (dec!("1") - self.fee),
+                d,
+                amplification,
+            );
+
+            // Like Curve, hold back one unit so that the error left by Newton's method stays in the pool
+            let output_amount = output_reserve - new_output_reserve - epsilon();
+            if output_amount.is_negative() {
+                Decimal::zero()
+            } else {
+                output_amount
+            }
+        }
+
+        /// Returns the amounts of token A and B that `remove_liquidity` would return for the given LP amount.
+        pub fn get_lp_value(&self, lp_amount: Decimal) -> (Decimal, Decimal) {
+            // Calculate the share based on the LP amount
+            let share =
+                lp_amount / borrow_resource_manager!(self.lp_resource_address).total_supply();
+
+            (self.a_pool.amount() * share, self.b_pool.amount() * share)
+        }
+
+        /// Returns the current amplification coefficient, moving linearly towards the future one while ramping.
+        pub fn get_amplification(&self) -> Decimal {
+            let current_epoch = Runtime::current_epoch();
+            if current_epoch >= self.ramp_end_epoch {
+                return self.future_amplification;
+            }
+
+            let elapsed = current_epoch - self.ramp_start_epoch;
+            let duration = self.ramp_end_epoch - self.ramp_start_epoch;
+            self.initial_amplification
+                + (self.future_amplification - self.initial_amplification) * elapsed / duration
+        }
+
+        /// Starts moving the amplification coefficient towards `future_amplification`, reaching it at `end_epoch`.
+        pub fn ramp_amplification(&mut self, future_amplification: Decimal, end_epoch: u64) {
+            let current_epoch = Runtime::current_epoch();
+            let current_amplification = self.get_amplification();
+            assert!(
+                end_epoch >= current_epoch + MIN_RAMP_EPOCHS,
+                "Ramp must take at least {} epochs",
+                MIN_RAMP_EPOCHS
+            );
+            assert!(
+                future_amplification > dec!("0")
+                    && future_amplification <= Decimal::from(MAX_AMPLIFICATION),
+                "Invalid amplification coefficient"
+            );
+            assert!(
+                future_amplification <= current_amplification * MAX_AMPLIFICATION_CHANGE
+                    && future_amplification * MAX_AMPLIFICATION_CHANGE >= current_amplification,
+                "Amplification coefficient may change by at most {}x per ramp",
+                MAX_AMPLIFICATION_CHANGE
+            );
+
+            self.initial_amplification = current_amplification;
+            self.future_amplification = future_amplification;
+            self.ramp_start_epoch = current_epoch;
+            self.ramp_end_epoch = end_epoch;
+        }
+
+        /// Stops the current ramp, keeping the amplification coefficient where it is now.
+        pub fn stop_ramp_amplification(&mut self) {
+            let current_amplification = self.get_amplification();
+            let current_epoch = Runtime::current_epoch();
+
+            self.initial_amplification = current_amplification;
+            self.future_amplification = current_amplification;
+            self.ramp_start_epoch = current_epoch;
+            self.ramp_end_epoch = current_epoch;
+        }
+
+        /// Returns the owner badge resource address.
+        pub fn owner_badge_address(&self) -> ResourceAddress {
+            self.owner_badge
+        }
+    }
+}
+
+/// Returns the StableSwap invariant D of a two-asset pool, solving
+/// `4A(x + y) + D = 4AD + D^3 / (4xy)` with Newton's method.
+fn calculate_d(x: Decimal, y: Decimal, amplification: Decimal) -> Decimal {
+    let sum = x + y;
+    if sum.is_zero() {
+        return Decimal::zero();
+    }
+
+    let ann = amplification * 4;
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        // D^3 / (4xy), computed step by step to keep intermediate values small
+        let d_p = d * d / (x * 2) * d / (y * 2);
+        let previous_d = d;
+        d = (ann * sum + d_p * 2) * d / ((ann - 1) * d + d_p * 3);
+        if (d - previous_d).abs() <= epsilon() {
+            return d;
+        }
+    }
+    panic!("Invariant did not converge");
+}
+
+/// Returns the reserve y which keeps the invariant at D when the other reserve is x.
+fn calculate_y(x: Decimal, d: Decimal, amplification: Decimal) -> Decimal {
+    let ann = amplification * 4;
+    let c = d * d / (x * 2) * d / (ann * 2);
+    let b = x + d / ann;
+
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let previous_y = y;
+        y = (y * y + c) / (y * 2 + b - d);
+        if (y - previous_y).abs() <= epsilon() {
+            return y;
+        }
+    }
+    panic!("Invariant did not converge");
+}
@@ -0,0 +1,419 @@
+use crate::math::{div_up, pow_down, pow_up};
+use scrypto::prelude::*;
+
+/// The maximum number of assets in a pool.
+const MAX_ASSETS: usize = 8;
+
+/// The smallest normalized weight an asset may have.
+fn min_weight() -> Decimal {
+    dec!("0.01")
+}
+
+/// The largest share of an asset's reserve that can be swapped in at once.
+fn max_in_ratio() -> Decimal {
+    dec!("0.5")
+}
+
+/// The largest share of an asset's reserve that can be taken out at once.
+fn max_out_ratio() -> Decimal {
+    dec!("0.3")
+}
+
+#[blueprint]
+mod weighted_pool {
+    /// A Balancer-style pool of up to 8 assets, each with its own weight. Prices follow the invariant
+    /// `prod(balance_i ^ weight_i) = k`, so a 50/50 two-asset pool behaves like Radiswap.
+    struct WeightedPool {
+        /// The resource address of LP token.
+        lp_resource_address: ResourceAddress,
+        /// LP tokens mint badge.
+        lp_mint_badge: Vault,
+        /// The resource addresses of the assets, in the order they were provided.
+        assets: Vec<ResourceAddress>,
+        /// The reserve for each asset.
+        pools: HashMap<ResourceAddress, Vault>,
+        /// The normalized weight of each asset, summing up to 1.
+        weights: HashMap<ResourceAddress, Decimal>,
+        /// The fee to apply for every swap
+        fee: Decimal,
+        /// The LP tokens minted for the first deposit, at instantiation or once the pool has been emptied.
+        lp_initial_supply: Decimal,
+    }
+
+    impl WeightedPool {
+        /// Creates a WeightedPool component from the given assets and their weights, and returns the component
+        /// address along with the initial LP tokens. Weights are normalized, so `[80, 20]` and `[0.8, 0.2]` are the
+        /// same.
+        pub fn instantiate_pool(
+            tokens: Vec<Bucket>,
+            weights: Vec<Decimal>,
+            lp_initial_supply: Decimal,
+            lp_symbol: String,
+            lp_name: String,
+            lp_url: String,
+            fee: Decimal,
+        ) -> (ComponentAddress, Bucket) {
+            // Check arguments
+            assert!(
+                tokens.len() >= 2 && tokens.len() <= MAX_ASSETS,
+                "A pool must have between 2 and {} assets",
+                MAX_ASSETS
+            );
+            assert!(
+                tokens.len() == weights.len(),
+                "You must pass in one weight per asset"
+            );
+            assert!(
+                tokens.iter().all(|tokens| !tokens.is_empty()),
+                "You must pass in an initial supply of each token"
+            );
+            assert!(
+                fee >= dec!("0") && fee <= dec!("1"),
+                "Invalid fee in thousandths"
+            );
+
+            assert!(
+                weights.iter().all(|weight| *weight > Decimal::zero()),
+                "Every weight must be positive"
+            );
+            let total_weight = weights
+                .iter()
+                .fold(Decimal::zero(), |total, weight| total + *weight);
+            assert!(
+                weights
+                    .iter()
+                    .all(|weight| *weight / total_weight >= min_weight()),
+                "Every weight must be at least {} of the total",
+                min_weight()
+            );
+
+            // Instantiate our LP token and mint an initial supply of them
+            let lp_mint_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "LP Token Mint Auth")
+                .mint_initial_supply(1);
+            let lp_resource_address = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_MAXIMUM)
+                .metadata("symbol", lp_symbol)
+                .metadata("name", lp_name)
+                .metadata("url", lp_url)
+                .mintable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                .burnable(rule!(require(lp_mint_badge.resource_address())), LOCKED)
+                .create_with_no_initial_supply();
+
+            let lp_tokens = lp_mint_badge.authorize(|| {
+                borrow_resource_manager!(lp_resource_address).mint(lp_initial_supply)
+            });
+
+            let mut assets = Vec::new();
+            let mut pools = HashMap::new();
+            let mut normalized_weights = HashMap::new();
+            for (tokens, weight) in tokens.into_iter().zip(weights.into_iter()) {
+                let resource_address = tokens.resource_address();
+                assert!(
+                    !pools.contains_key(&resource_address),
+                    "Asset {:?} was passed in more than once",
+                    resource_address
+                );
+                assets.push(resource_address);
+                pools.insert(resource_address, Vault::with_bucket(tokens));
+                normalized_weights.insert(resource_address, weight / total_weight);
+            }
+
+            // Instantiate our WeightedPool component
+            let weighted_pool = Self {
+                lp_resource_address,
+                lp_mint_badge: Vault::with_bucket(lp_mint_badge),
+                assets,
+                pools,
+                weights: normalized_weights,
+                fee,
+                lp_initial_supply,
+            }
+            .instantiate()
+            .globalize();
+
+            // Return the new WeightedPool component, as well as the initial supply of LP tokens
+            (weighted_pool, lp_tokens)
+        }
+
+        /// Adds liquidity in proportion to the current reserves and returns the LP tokens representing pool shares
+        /// along with any remainder.
+        pub fn add_liquidity(&mut self, tokens: Vec<Bucket>) -> (Bucket, Vec<Bucket>) {
+            assert!(
+                tokens.len() == self.assets.len()
+                    && tokens
+                        .iter()
+                        .map(|tokens| tokens.resource_address())
+                        .collect::<HashSet<ResourceAddress>>()
+                        .len()
+                        == self.assets.len(),
+                "You must pass in one bucket per asset"
+            );
+
+            // Get the resource manager of the lp tokens
+            let lp_resource_manager = borrow_resource_manager!(self.lp_resource_address);
+
+            // A deposit into an emptied pool sets the prices anew and gets the initial LP supply, like at instantiation
+            if lp_resource_manager.total_supply() == 0.into() {
+                assert!(
+                    tokens.iter().all(|tokens| !tokens.is_empty()),
+                    "You must pass in some of each token"
+                );
+                let mut remainders = Vec::new();
+                for mut tokens in tokens {
+                    let pool = self.pools.get_mut(&tokens.resource_address()).unwrap();
+                    pool.put(tokens.take(tokens.amount()));
+                    remainders.push(tokens);
+                }
+                let lp_initial_supply = self.lp_initial_supply;
+                let lp_tokens = self
+                    .lp_mint_badge
+                    .authorize(|| lp_resource_manager.mint(lp_initial_supply));
+                return (lp_tokens, remainders);
+            }
+
+            // The smallest ratio of added liquidity in existing liquidity decides the share
+            let mut ratio = tokens[0].amount() / self.get_reserve(tokens[0].resource_address());
+            for tokens in tokens.iter().skip(1) {
+                let token_ratio = tokens.amount() / self.get_reserve(tokens.resource_address());
+                if token_ratio < ratio {
+                    ratio = token_ratio;
+                }
+            }
+
+            // Claim the correct amount of each token
+            let mut remainders = Vec::new();
+            for mut tokens in tokens {
+                let pool = self.pools.get_mut(&tokens.resource_address()).unwrap();
+                pool.put(tokens.take(pool.amount() * ratio));
+                remainders.push(tokens);
+            }
+
+            // Mint LP tokens according to the share the provider is contributing
+            let supply_to_mint = lp_resource_manager.total_supply() * ratio;
+            let lp_tokens = self
+                .lp_mint_badge
+                .authorize(|| lp_resource_manager.mint(supply_to_mint));
+
+            // Return the LP tokens along with any remainder
+            (lp_tokens, remainders)
+        }
+
+        /// Removes liquidity in proportion to the current reserves.
+        pub fn remove_liquidity(&mut self, lp_tokens: Bucket) -> Vec<Bucket> {
+            assert!(
+                self.lp_resource_address == lp_tokens.resource_address(),
+                "Wrong token type passed in"
+            );
+
+            // Calculate the share based on the input LP tokens.
+            let share = lp_tokens.amount()
+                / borrow_resource_manager!(self.lp_resource_address).total_supply();
+
+            // Withdraw the correct amounts of each token from reserves
+            let withdrawn = self
+                .assets
+                .iter()
+                .map(|resource_address| {
+                    let pool = self.pools.get_mut(resource_address).unwrap();
+                    pool.take(pool.amount() * share)
+                })
+                .collect();
+
+            // Burn the LP tokens received
+            self.lp_mint_badge.authorize(|| {
+                lp_tokens.burn();
+            });
+
+            // Return the withdrawn tokens
+            withdrawn
+        }
+
+        /// Adds liquidity in a single asset and returns the LP tokens representing pool shares.
+        /// The part of the deposit which is effectively swapped into the other assets pays the swap fee.
+        pub fn add_single_asset_liquidity(&mut self, tokens: Bucket) -> Bucket {
+            let lp_resource_manager = borrow_resource_manager!(self.lp_resource_address);
+            let resource_address = tokens.resource_address();
+            let reserve = self.get_reserve(resource_address);
+            let weight = self.get_weight(resource_address);
+            assert!(
+                tokens.amount() <= reserve * max_in_ratio(),
+                "Deposit exceeds {} of the reserve",
+                max_in_ratio()
+            );
+
+            // lp_out = supply * ((1 + amount_after_fee / reserve) ^ weight - 1), rounded down
+            let amount_after_fee = tokens.amount() * (dec!("1") - self.fee * (dec!("1") - weight));
+            let growth = pow_down(dec!("1") + amount_after_fee / reserve, weight);
+            let supply_to_mint = if growth > dec!("1") {
+                lp_resource_manager.total_supply() * (growth - dec!("1"))
+            } else {
+                Decimal::zero()
+            };
+
+            self.pools.get_mut(&resource_address).unwrap().put(tokens);
+            self.lp_mint_badge
+                .authorize(|| lp_resource_manager.mint(supply_to_mint))
+        }
+
+        /// Removes liquidity into a single asset.
+        /// The part of the withdrawal which is effectively swapped from the other assets pays the swap fee.
+        pub fn remove_single_asset_liquidity(
+            &mut self,
+            lp_tokens: Bucket,
+            output_resource: ResourceAddress,
+        ) -> Bucket {
+            assert!(
+                self.lp_resource_address == lp_tokens.resource_address(),
+                "Wrong token type passed in"
+            );
+            let lp_supply = borrow_resource_manager!(self.lp_resource_address).total_supply();
+            let reserve = self.get_reserve(output_resource);
+            let weight = self.get_weight(output_resource);
+            let fee_factor = dec!("1") - self.fee * (dec!("1") - weight);
+            assert!(
+                lp_tokens.amount() < lp_supply,
+                "The whole supply can't be redeemed into a single asset"
+            );
+
+            // Since (1 - share) ^ (1 / weight) <= 1 - share, the output is at least the share of the reserve less the
+            // fee, which rules out large withdrawals before evaluating the power
+            let share = lp_tokens.amount() / lp_supply;
+            assert!(
+                share * fee_factor <= max_out_ratio(),
+                "Withdrawal exceeds {} of the reserve",
+                max_out_ratio()
+            );
+
+            // out = reserve * (1 - (1 - share) ^ (1 / weight)), less the fee, rounded down
+            let remaining = pow_up(dec!("1") - share, dec!("1") / weight);
+            let amount_before_fee = if remaining < dec!("1") {
+                reserve * (dec!("1") - remaining)
+            } else {
+                Decimal::zero()
+            };
+            let output_amount = amount_before_fee * fee_factor;
+            assert!(
+                output_amount <= reserve * max_out_ratio(),
+                "Withdrawal exceeds {} of the reserve",
+                max_out_ratio()
+            );
+
+            // Burn the LP tokens received
+            self.lp_mint_badge.authorize(|| {
+                lp_tokens.burn();
+            });
+
+            self.pools
+                .get_mut(&output_resource)
+                .unwrap()
+                .take(output_amount)
+        }
+
+        /// Swaps the input tokens for any other asset of the pool.
+        pub fn swap(&mut self, input_tokens: Bucket, output_resource: ResourceAddress) -> Bucket {
+            // Calculate how much of the output token we will return
+            let output_amount = self.get_amount_out(
+                input_tokens.resource_address(),
+                input_tokens.amount(),
+                output_resource,
+            );
+
+            // Put the input tokens into our pool
+            self.pools
+                .get_mut(&input_tokens.resource_address())
+                .unwrap()
+                .put(input_tokens);
+
+            // Return the tokens owed
+            self.pools
+                .get_mut(&output_resource)
+                .unwrap()
+                .take(output_amount)
+        }
+
+        /// Returns how much of OUTPUT `swap` would return for the given input.
+        pub fn get_amount_out(
+            &self,
+            input_resource: ResourceAddress,
+            input_amount: Decimal,
+            output_resource: ResourceAddress,
+        ) -> Decimal {
+            assert!(
+                input_resource != output_resource,
+                "Input and output must be different assets"
+            );
+            let input_reserve = self.get_reserve(input_resource);
+            let output_reserve = self.get_reserve(output_resource);
+            assert!(
+                input_amount <= input_reserve * max_in_ratio(),
+                "Input exceeds {} of the reserve",
+                max_in_ratio()
+            );
+
+            // out = output_reserve * (1 - (input_reserve / (input_reserve + amount_after_fee)) ^ (w_in / w_out)),
+            // rounded down
+            let amount_after_fee = input_amount * (dec!("1") - self.fee);
+            let remaining = pow_up(
+                div_up(input_reserve, input_reserve + amount_after_fee),
+                self.get_weight(input_resource) / self.get_weight(output_resource),
+            );
+            let output_amount = if remaining < dec!("1") {
+                output_reserve * (dec!("1") - remaining)
+            } else {
+                Decimal::zero()
+            };
+            assert!(
+                output_amount <= output_reserve * max_out_ratio(),
+                "Output exceeds {} of the reserve",
+                max_out_ratio()
+            );
+            output_amount
+        }
+
+        /// Returns the marginal price of BASE, expressed in QUOTE.
+        pub fn get_spot_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Decimal {
+            (self.get_reserve(quote) / self.get_weight(quote))
+                / (self.get_reserve(base) / self.get_weight(base))
+        }
+
+        /// Returns the resource addresses of the assets.
+        pub fn get_assets(&self) -> Vec<ResourceAddress> {
+            self.assets.clone()
+        }
+
+        /// Returns the normalized weights of the assets, in the same order as `get_assets`.
+        pub fn get_weights(&self) -> Vec<Decimal> {
+            self.assets
+                .iter()
+                .map(|resource_address| self.get_weight(*resource_address))
+                .collect()
+        }
+
+        /// Returns the reserves of the assets, in the same order as `get_assets`.
+        pub fn get_reserves(&self) -> Vec<Decimal> {
+            self.assets
+                .iter()
+                .map(|resource_address| self.get_reserve(*resource_address))
+                .collect()
+        }
+
+        /// Returns the reserve of an asset.
+        fn get_reserve(&self, resource_address: ResourceAddress) -> Decimal {
+            self.pools
+                .get(&resource_address)
+                .unwrap_or_else(|| {
+                    panic!("Resource {:?} is not part of this pool", resource_address)
+                })
+                .amount()
+        }
+
+        /// Returns the normalized weight of an asset.
+        fn get_weight(&self, resource_address: ResourceAddress) -> Decimal {
+            *self.weights.get(&resource_address).unwrap_or_else(|| {
+                panic!("Resource {:?} is not part of this pool", resource_address)
+            })
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    concentrated_pool: ComponentAddress,
+    token_a: ResourceAddress,
+    token_b: ResourceAddress,
+    position: ResourceAddress,
+}
+
+/// Sets up a ConcentratedPool without liquidity at a price of 1, with a 0.3% fee and a tick spacing of 10.
+fn setup() -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the tokens of the pair
+    let token_a = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let token_b = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_pool` function.
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            package_address,
+            "ConcentratedPool",
+            "instantiate_pool",
+            manifest_args!(token_a, token_b, dec!("1"), dec!("0.003"), 10i32),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let concentrated_pool = receipt.expect_commit(true).new_component_addresses()[0];
+    let position = receipt.expect_commit(true).new_resource_addresses()[1];
+
+    TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        concentrated_pool,
+        token_a,
+        token_b,
+        position,
+    }
+}
+
+fn execute(
+    env: &mut TestEnv,
+    manifest: TransactionManifest,
+    public_key: EcdsaSecp256k1PublicKey,
+) -> TransactionReceipt {
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Creates a second account holding 1,000 A and 1,000 B.
+fn new_funded_account(env: &mut TestEnv) -> (EcdsaSecp256k1PublicKey, ComponentAddress) {
+    let (public_key, _private_key, account_component) = env.test_runner.new_allocated_account();
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.token_a, dec!("1000"))
+        .withdraw_from_account(env.account_component, env.token_b, dec!("1000"))
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let owner = env.public_key;
+    execute(env, manifest, owner).expect_commit_success();
+    (public_key, account_component)
+}
+
+/// Provides liquidity from an account between two ticks, and asserts at least `min_back` of A and B is returned.
+fn add_position(
+    env: &mut TestEnv,
+    account: (EcdsaSecp256k1PublicKey, ComponentAddress),
+    a_amount: Decimal,
+    b_amount: Decimal,
+    ticks: (i32, i32),
+    min_back: (Decimal, Decimal),
+) -> TransactionReceipt {
+    let (public_key, account_component) = account;
+    let (lower_tick, upper_tick) = ticks;
+    let (min_a_back, min_b_back) = min_back;
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account_component, env.token_a, a_amount)
+        .withdraw_from_account(account_component, env.token_b, b_amount)
+        .take_from_worktop(env.token_a, |builder, a_bucket| {
+            builder.take_from_worktop(env.token_b, |builder, b_bucket| {
+                builder.call_method(
+                    env.concentrated_pool,
+                    "add_position",
+                    manifest_args!(a_bucket, b_bucket, lower_tick, upper_tick),
+                )
+            })
+        })
+        .assert_worktop_contains_by_amount(dec!("1"), env.position)
+        .assert_worktop_contains_by_amount(min_a_back, env.token_a)
+        .assert_worktop_contains_by_amount(min_b_back, env.token_b)
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest, public_key)
+}
+
+/// Provides 100 A and 100 B of liquidity between ticks -1000 and 1000, around the price of 1.
+fn add_position_around_price(env: &mut TestEnv) {
+    let owner = (env.public_key, env.account_component);
+    add_position(
+        env,
+        owner,
+        dec!("100"),
+        dec!("100"),
+        (-1000, 1000),
+        (dec!("0"), dec!("0")),
+    )
+    .expect_commit_success();
+}
+
+/// Closes the position the account holds, and asserts at least `min_a` and `min_b` of the tokens are returned.
+fn remove_position(env: &mut TestEnv, min_a: Decimal, min_b: Decimal) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.position, dec!("1"))
+        .take_from_worktop(env.position, |builder, bucket| {
+            builder.call_method(
+                env.concentrated_pool,
+                "remove_position",
+                manifest_args!(bucket),
+            )
+        })
+        .assert_worktop_contains_by_amount(min_a, env.token_a)
+        .assert_worktop_contains_by_amount(min_b, env.token_b)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let owner = env.public_key;
+    execute(env, manifest, owner)
+}
+
+/// Swaps `input_amount` of `input` and asserts at least `min_output` of `output` is returned.
+fn swap(
+    env: &mut TestEnv,
+    input: ResourceAddress,
+    input_amount: Decimal,
+    output: ResourceAddress,
+    min_output: Decimal,
+) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, input, input_amount)
+        .take_from_worktop(input, |builder, bucket| {
+            builder.call_method(env.concentrated_pool, "swap", manifest_args!(bucket))
+        })
+        .assert_worktop_contains_by_amount(min_output, output)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let owner = env.public_key;
+    execute(env, manifest, owner)
+}
+
+/// Collects the fees of the position the account holds, and asserts at least `min_a` of token A is returned.
+fn collect_fees(env: &mut TestEnv, min_a: Decimal) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, env.position)
+        .pop_from_auth_zone(|builder, proof| {
+            builder.call_method(env.concentrated_pool, "collect_fees", manifest_args!(proof))
+        })
+        .assert_worktop_contains_by_amount(min_a, env.token_a)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let owner = env.public_key;
+    execute(env, manifest, owner)
+}
+
+#[test]
+fn test_position_around_price_takes_both_tokens() {
+    let mut env = setup();
+    let owner = (env.public_key, env.account_component);
+
+    // The range is symmetric around the price, so it takes as much B as A and returns the other ~50 B
+    add_position(
+        &mut env,
+        owner,
+        dec!("100"),
+        dec!("150"),
+        (-1000, 1000),
+        (dec!("0"), dec!("49.9999")),
+    )
+    .expect_commit_success();
+
+    let mut env = setup();
+    let owner = (env.public_key, env.account_component);
+    add_position(
+        &mut env,
+        owner,
+        dec!("100"),
+        dec!("150"),
+        (-1000, 1000),
+        (dec!("0"), dec!("50.0001")),
+    )
+    .expect_commit_failure();
+}
+
+#[test]
+fn test_position_above_price_takes_only_a() {
+    let mut env = setup();
+    let owner = (env.public_key, env.account_component);
+
+    add_position(
+        &mut env,
+        owner,
+        dec!("100"),
+        dec!("100"),
+        (1000, 2000),
+        (dec!("0"), dec!("100")),
+    )
+    .expect_commit_success();
+
+    // Closing it right away returns the A, rounded down
+    remove_position(&mut env, dec!("99.9999"), dec!("0")).expect_commit_success();
+}
+
+#[test]
+fn test_position_below_price_takes_only_b() {
+    let mut env = setup();
+    let owner = (env.public_key, env.account_component);
+
+    add_position(
+        &mut env,
+        owner,
+        dec!("100"),
+        dec!("100"),
+        (-2000, -1000),
+        (dec!("100"), dec!("0")),
+    )
+    .expect_commit_success();
+
+    remove_position(&mut env, dec!("0"), dec!("99.9999")).expect_commit_success();
+}
+
+#[test]
+fn test_remove_position_never_returns_more_than_deposited() {
+    let mut env = setup();
+    add_position_around_price(&mut env);
+
+    remove_position(&mut env, dec!("100.000000000000000001"), dec!("0")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_within_range() {
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    add_position_around_price(&mut env);
+
+    // L = ~2050.5166, so 10 A in moves the square root price from 1 to L / (L + 9.97) and releases ~9.9218 B
+    swap(&mut env, token_a, dec!("10"), token_b, dec!("9.9217")).expect_commit_success();
+
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    add_position_around_price(&mut env);
+    swap(&mut env, token_a, dec!("10"), token_b, dec!("9.9218")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_fails_beyond_liquidity() {
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    add_position_around_price(&mut env);
+
+    // Moving the price down to tick -1000 only takes ~105.44 A, past which there is no liquidity
+    swap(&mut env, token_a, dec!("150"), token_b, dec!("0")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_crosses_tick_into_next_position() {
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    let owner = (env.public_key, env.account_component);
+    add_position_around_price(&mut env);
+    add_position(
+        &mut env,
+        owner,
+        dec!("100"),
+        dec!("100"),
+        (-2000, -1000),
+        (dec!("100"), dec!("0")),
+    )
+    .expect_commit_success();
+
+    // ~105.44 A takes the ~100 B of the first position, then crossing tick -1000 activates the second position
+    // which releases ~39.42 B for the remaining ~44.56 A
+    swap(&mut env, token_a, dec!("150"), token_b, dec!("139.4231")).expect_commit_success();
+
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    let owner = (env.public_key, env.account_component);
+    add_position_around_price(&mut env);
+    add_position(
+        &mut env,
+        owner,
+        dec!("100"),
+        dec!("100"),
+        (-2000, -1000),
+        (dec!("100"), dec!("0")),
+    )
+    .expect_commit_success();
+    swap(&mut env, token_a, dec!("150"), token_b, dec!("139.4232")).expect_commit_failure();
+}
+
+#[test]
+fn test_remove_position_after_swap() {
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    add_position_around_price(&mut env);
+    swap(&mut env, token_a, dec!("10"), token_b, dec!("9.9217")).expect_commit_success();
+
+    // The position now holds ~109.97 A and ~90.0782 B, plus the 0.03 A fee
+    remove_position(&mut env, dec!("109.9999"), dec!("90.0782")).expect_commit_success();
+}
+
+#[test]
+fn test_collect_fees() {
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    add_position_around_price(&mut env);
+    swap(&mut env, token_a, dec!("10"), token_b, dec!("9.9217")).expect_commit_success();
+
+    // The only position in range earns the whole 0.3% fee of 10 A, rounded down
+    collect_fees(&mut env, dec!("0.029999")).expect_commit_success();
+
+    // Once collected, there is nothing left to collect
+    collect_fees(&mut env, dec!("0.000001")).expect_commit_failure();
+}
+
+#[test]
+fn test_position_out_of_range_earns_no_fees() {
+    let mut env = setup();
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    let owner = (env.public_key, env.account_component);
+
+    // This account's position is above the price, while another account provides the liquidity swaps use
+    add_position(
+        &mut env,
+        owner,
+        dec!("100"),
+        dec!("100"),
+        (1000, 2000),
+        (dec!("0"), dec!("100")),
+    )
+    .expect_commit_success();
+    let other = new_funded_account(&mut env);
+    add_position(
+        &mut env,
+        other,
+        dec!("100"),
+        dec!("100"),
+        (-1000, 1000),
+        (dec!("0"), dec!("0")),
+    )
+    .expect_commit_success();
+
+    swap(&mut env, token_a, dec!("10"), token_b, dec!("9.9217")).expect_commit_success();
+
+    collect_fees(&mut env, dec!("0.000001")).expect_commit_failure();
+}
@@ -0,0 +1,459 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    radiswap: ComponentAddress,
+    token_a: ResourceAddress,
+    token_b: ResourceAddress,
+    lp_token: ResourceAddress,
+    flash_swap_receipt: ResourceAddress,
+    owner_badge: ResourceAddress,
+}
+
+/// Sets up a Radiswap pool with 1,000 A and 1,000 B, and a 0.3% fee.
+fn setup() -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the tokens of the pair
+    let token_a = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let token_b = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_pool` function.
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account_component, token_a, dec!("1000"))
+        .withdraw_from_account(account_component, token_b, dec!("1000"))
+        .take_from_worktop(token_a, |builder, a_bucket| {
+            builder.take_from_worktop(token_b, |builder, b_bucket| {
+                builder.call_function(
+                    package_address,
+                    "Radiswap",
+                    "instantiate_pool",
+                    manifest_args!(
+                        a_bucket,
+                        b_bucket,
+                        dec!("1000"),
+                        "LP",
+                        "LP Token",
+                        "https://example.com/",
+                        dec!("0.003")
+                    ),
+                )
+            })
+        })
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let radiswap = receipt.expect_commit(true).new_component_addresses()[0];
+    let lp_token = receipt.expect_commit(true).new_resource_addresses()[1];
+    let flash_swap_receipt = receipt.expect_commit(true).new_resource_addresses()[2];
+    let owner_badge = receipt.expect_commit(true).new_resource_addresses()[3];
+
+    TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        radiswap,
+        token_a,
+        token_b,
+        lp_token,
+        flash_swap_receipt,
+        owner_badge,
+    }
+}
+
+fn swap_with_limits(env: &mut TestEnv, min_output: Decimal, deadline: u64) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.token_a, dec!("100"))
+        .take_from_worktop(env.token_a, |builder, bucket| {
+            builder.call_method(
+                env.radiswap,
+                "swap_with_limits",
+                manifest_args!(bucket, min_output, deadline),
+            )
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+#[test]
+fn test_swap_with_limits() {
+    let mut env = setup();
+
+    // 100 A in, 0.3% fee: 1000 - 1000 * 1000 / 1099.7 = ~90.66 B out
+    let receipt = swap_with_limits(&mut env, dec!("90"), 10);
+    receipt.expect_commit_success();
+}
+
+#[test]
+fn test_swap_fails_below_min_output() {
+    let mut env = setup();
+
+    let receipt = swap_with_limits(&mut env, dec!("91"), 10);
+    receipt.expect_commit_failure();
+}
+
+#[test]
+fn test_swap_fails_after_deadline() {
+    let mut env = setup();
+    env.test_runner.set_current_epoch(11);
+
+    let receipt = swap_with_limits(&mut env, dec!("90"), 10);
+    receipt.expect_commit_failure();
+}
+
+#[test]
+fn test_add_liquidity_fails_below_min_lp_tokens() {
+    let mut env = setup();
+
+    // 100 A and 100 B is a 10% share, which mints 100 LP tokens
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.token_a, dec!("100"))
+        .withdraw_from_account(env.account_component, env.token_b, dec!("100"))
+        .take_from_worktop(env.token_a, |builder, a_bucket| {
+            builder.take_from_worktop(env.token_b, |builder, b_bucket| {
+                builder.call_method(
+                    env.radiswap,
+                    "add_liquidity_with_limits",
+                    manifest_args!(a_bucket, b_bucket, dec!("101"), 10u64),
+                )
+            })
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_failure();
+}
+
+#[test]
+fn test_remove_liquidity_fails_below_min_amounts() {
+    let mut env = setup();
+
+    // 100 LP tokens are a 10% share, which withdraws 100 A and 100 B
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.lp_token, dec!("100"))
+        .take_from_worktop(env.lp_token, |builder, bucket| {
+            builder.call_method(
+                env.radiswap,
+                "remove_liquidity_with_limits",
+                manifest_args!(bucket, dec!("100"), dec!("101"), 10u64),
+            )
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_failure();
+}
+
+fn set_protocol_fee_share(env: &mut TestEnv, as_owner: bool) -> TransactionReceipt {
+    let mut builder = ManifestBuilder::new();
+    if as_owner {
+        builder.create_proof_from_account(env.account_component, env.owner_badge);
+    }
+    let manifest = builder
+        .call_method(
+            env.radiswap,
+            "set_protocol_fee_share",
+            manifest_args!(dec!("0.5")),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+fn withdraw_protocol_fees(
+    env: &mut TestEnv,
+    as_owner: bool,
+    min_fees_a: Decimal,
+) -> TransactionReceipt {
+    let mut builder = ManifestBuilder::new();
+    if as_owner {
+        builder.create_proof_from_account(env.account_component, env.owner_badge);
+    }
+    let manifest = builder
+        .call_method(env.radiswap, "withdraw_protocol_fees", manifest_args!())
+        .assert_worktop_contains_by_amount(min_fees_a, env.token_a)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+#[test]
+fn test_protocol_fee_share_requires_owner_badge() {
+    let mut env = setup();
+
+    set_protocol_fee_share(&mut env, false).expect_commit_failure();
+    set_protocol_fee_share(&mut env, true).expect_commit_success();
+}
+
+#[test]
+fn test_protocol_fees_accrue_to_owner() {
+    let mut env = setup();
+    set_protocol_fee_share(&mut env, true).expect_commit_success();
+
+    // Half of the 0.3% fee on 100 A goes to the protocol: 0.15 A. The output is unaffected, ~90.66 B
+    swap_with_limits(&mut env, dec!("90"), 10).expect_commit_success();
+
+    withdraw_protocol_fees(&mut env, false, dec!("0")).expect_commit_failure();
+    withdraw_protocol_fees(&mut env, true, dec!("0.16")).expect_commit_failure();
+    withdraw_protocol_fees(&mut env, true, dec!("0.15")).expect_commit_success();
+}
+
+fn swap_exact_output(
+    env: &mut TestEnv,
+    output_amount: Decimal,
+    max_input: Decimal,
+) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.token_a, dec!("200"))
+        .take_from_worktop(env.token_a, |builder, bucket| {
+            builder.call_method(
+                env.radiswap,
+                "swap_exact_output",
+                manifest_args!(bucket, output_amount, max_input),
+            )
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+#[test]
+fn test_swap_exact_output() {
+    let mut env = setup();
+
+    // 100 B out, 0.3% fee: (1000 * 1000 / 900 - 1000) / 0.997 = ~111.45 A in
+    let receipt = swap_exact_output(&mut env, dec!("100"), dec!("112"));
+    receipt.expect_commit_success();
+}
+
+#[test]
+fn test_swap_exact_output_fails_above_max_input() {
+    let mut env = setup();
+
+    let receipt = swap_exact_output(&mut env, dec!("100"), dec!("111"));
+    receipt.expect_commit_failure();
+}
+
+fn flash_swap(env: &mut TestEnv, repayment: Decimal) -> TransactionReceipt {
+    // Borrow 100 B and repay it in A
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.radiswap,
+            "flash_swap",
+            manifest_args!(dec!("0"), dec!("100")),
+        )
+        .withdraw_from_account(env.account_component, env.token_a, repayment)
+        .take_from_worktop(env.token_a, |builder, a_bucket| {
+            builder.take_from_worktop_by_amount(dec!("0"), env.token_b, |builder, b_bucket| {
+                builder.take_from_worktop(env.flash_swap_receipt, |builder, receipt_bucket| {
+                    builder.call_method(
+                        env.radiswap,
+                        "settle_flash_swap",
+                        manifest_args!(a_bucket, b_bucket, receipt_bucket),
+                    )
+                })
+            })
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+#[test]
+fn test_flash_swap() {
+    let mut env = setup();
+
+    // 100 B out, 0.3% fee: (1000 * 1000 / 900 - 1000) / 0.997 = ~111.45 A in
+    let receipt = flash_swap(&mut env, dec!("112"));
+    receipt.expect_commit_success();
+}
+
+#[test]
+fn test_flash_swap_fails_below_invariant() {
+    let mut env = setup();
+
+    let receipt = flash_swap(&mut env, dec!("111"));
+    receipt.expect_commit_failure();
+}
+
+#[test]
+fn test_flash_swap_fails_without_settlement() {
+    let mut env = setup();
+
+    // The receipt can't be deposited, so the transaction can't complete
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.radiswap,
+            "flash_swap",
+            manifest_args!(dec!("0"), dec!("100")),
+        )
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_failure();
+}
+
+#[test]
+fn test_swap_fails_during_flash_swap() {
+    let mut env = setup();
+
+    // Swapping against the pool before the flash swap is settled hits the lock, even if it's settled afterwards
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.radiswap,
+            "flash_swap",
+            manifest_args!(dec!("0"), dec!("100")),
+        )
+        .withdraw_from_account(env.account_component, env.token_a, dec!("10"))
+        .take_from_worktop(env.token_a, |builder, bucket| {
+            builder.call_method(env.radiswap, "swap", manifest_args!(bucket))
+        })
+        .withdraw_from_account(env.account_component, env.token_a, dec!("112"))
+        .take_from_worktop(env.token_a, |builder, a_bucket| {
+            builder.take_from_worktop_by_amount(dec!("0"), env.token_b, |builder, b_bucket| {
+                builder.take_from_worktop(env.flash_swap_receipt, |builder, receipt_bucket| {
+                    builder.call_method(
+                        env.radiswap,
+                        "settle_flash_swap",
+                        manifest_args!(a_bucket, b_bucket, receipt_bucket),
+                    )
+                })
+            })
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_failure();
+}
+
+fn consult(env: &mut TestEnv, window_epochs: u64) -> Option<Decimal> {
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.radiswap,
+            "consult",
+            manifest_args!(env.token_a, env.token_b, window_epochs),
+        )
+        .build();
+    let receipt = env
+        .test_runner
+        .execute_manifest_ignoring_fee(manifest, vec![]);
+    println!("{:?}\n", receipt);
+    receipt.expect_commit(true).output(0)
+}
+
+#[test]
+fn test_consult_averages_prices_over_window() {
+    let mut env = setup();
+
+    // Each swap of 100 A lowers the price of A in B, to ~0.8267 and then to ~0.6948
+    env.test_runner.set_current_epoch(10);
+    swap_with_limits(&mut env, dec!("0"), 100).expect_commit_success();
+    env.test_runner.set_current_epoch(15);
+    swap_with_limits(&mut env, dec!("0"), 100).expect_commit_success();
+    env.test_runner.set_current_epoch(20);
+
+    // The last 5 epochs only saw the second price
+    let twap = consult(&mut env, 5).unwrap();
+    assert!(twap > dec!("0.6948") && twap < dec!("0.6949"));
+
+    // The last 10 epochs saw both for 5 epochs each
+    let twap = consult(&mut env, 10).unwrap();
+    assert!(twap > dec!("0.7607") && twap < dec!("0.7608"));
+
+    // An empty window has no average
+    assert_eq!(consult(&mut env, 0), None);
+}
@@ -0,0 +1,154 @@
+use radiswap::math::*;
+use scrypto::prelude::*;
+
+/// Asserts two numbers are within `tolerance` of each other.
+fn assert_close(actual: Decimal, expected: Decimal, tolerance: Decimal) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "{} is not within {} of {}",
+        actual,
+        tolerance,
+        expected
+    );
+}
+
+#[test]
+fn test_ln_of_known_values() {
+    assert_eq!(ln(dec!("1")), Decimal::zero());
+    assert_close(
+        ln(dec!("2")),
+        dec!("0.693147180559945309"),
+        dec!("0.00000000000000001"),
+    );
+    assert_close(
+        ln(dec!("2.718281828459045235")),
+        dec!("1"),
+        dec!("0.0000000000000001"),
+    );
+    assert_close(
+        ln(dec!("0.5")),
+        dec!("-0.693147180559945309"),
+        dec!("0.00000000000000001"),
+    );
+    assert_close(
+        ln(dec!("1000")),
+        dec!("6.907755278982137052"),
+        dec!("0.0000000000000001"),
+    );
+}
+
+#[test]
+#[should_panic]
+fn test_ln_of_zero_panics() {
+    ln(Decimal::zero());
+}
+
+#[test]
+fn test_exp_of_known_values() {
+    assert_eq!(exp(Decimal::zero()), dec!("1"));
+    assert_close(
+        exp(dec!("1")),
+        dec!("2.718281828459045235"),
+        dec!("0.00000000000001"),
+    );
+    assert_close(
+        exp(dec!("-1")),
+        dec!("0.367879441171442321"),
+        dec!("0.00000000000001"),
+    );
+    assert_close(
+        exp(dec!("10")),
+        dec!("22026.465794806716516957"),
+        dec!("0.000000001"),
+    );
+}
+
+#[test]
+fn test_exp_inverts_ln() {
+    for x in [
+        dec!("0.01"),
+        dec!("0.3"),
+        dec!("1.5"),
+        dec!("7"),
+        dec!("12345.678"),
+    ] {
+        assert_close(exp(ln(x)), x, x * dec!("0.00000000000001"));
+    }
+}
+
+#[test]
+fn test_pow_of_known_values() {
+    assert_eq!(pow(dec!("5"), Decimal::zero()), dec!("1"));
+    assert_eq!(pow(dec!("1"), dec!("3.7")), dec!("1"));
+    assert_close(
+        pow(dec!("2"), dec!("0.5")),
+        dec!("1.414213562373095048"),
+        dec!("0.00000000000001"),
+    );
+    assert_close(
+        pow(dec!("16"), dec!("0.25")),
+        dec!("2"),
+        dec!("0.00000000000001"),
+    );
+    assert_close(
+        pow(dec!("2"), dec!("10")),
+        dec!("1024"),
+        dec!("0.0000000001"),
+    );
+    assert_close(
+        pow(dec!("0.25"), dec!("0.5")),
+        dec!("0.5"),
+        dec!("0.00000000000001"),
+    );
+}
+
+#[test]
+fn test_pow_up_and_down_bracket_exact_values() {
+    let cases = [
+        (dec!("4"), dec!("0.5"), dec!("2")),
+        (dec!("16"), dec!("0.25"), dec!("2")),
+        (dec!("0.25"), dec!("0.5"), dec!("0.5")),
+        (dec!("1.21"), dec!("0.5"), dec!("1.1")),
+        (dec!("2"), dec!("10"), dec!("1024")),
+        (dec!("0.9"), dec!("2"), dec!("0.81")),
+    ];
+    for (base, exponent, exact) in cases {
+        assert!(
+            pow_down(base, exponent) <= exact,
+            "pow_down({}, {})",
+            base,
+            exponent
+        );
+        assert!(
+            pow_up(base, exponent) >= exact,
+            "pow_up({}, {})",
+            base,
+            exponent
+        );
+    }
+}
+
+#[test]
+fn test_powi() {
+    assert_eq!(powi(dec!("3"), 5), dec!("243"));
+    assert_eq!(powi(dec!("1.5"), 2), dec!("2.25"));
+    assert_eq!(powi(dec!("7"), 0), dec!("1"));
+}
+
+#[test]
+fn test_sqrt() {
+    assert_eq!(sqrt(Decimal::zero()), Decimal::zero());
+    assert_eq!(sqrt(dec!("16")), dec!("4"));
+    assert_eq!(sqrt(dec!("0.25")), dec!("0.5"));
+    assert_close(sqrt(dec!("2")), dec!("1.414213562373095048"), epsilon() * 2);
+}
+
+#[test]
+fn test_mul_up_and_div_up_round_up() {
+    assert_eq!(div_up(dec!("1"), dec!("3")), dec!("0.333333333333333334"));
+    assert_eq!(dec!("1") / dec!("3"), dec!("0.333333333333333333"));
+    assert_eq!(div_up(dec!("6"), dec!("3")), dec!("2"));
+    assert_eq!(mul_up(dec!("2"), dec!("3")), dec!("6"));
+    assert_eq!(mul_up(dec!("0.000000001"), dec!("0.0000000001")), epsilon());
+    assert_eq!(mul_up(dec!("5"), Decimal::zero()), Decimal::zero());
+}
@@ -0,0 +1,202 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    router: ComponentAddress,
+    admin_badge: ResourceAddress,
+    token_a: ResourceAddress,
+    token_b: ResourceAddress,
+    token_c: ResourceAddress,
+    token_d: ResourceAddress,
+}
+
+/// Sets up a RadiswapRouter with an A/B and a B/C pool registered, each with 1,000 of both tokens and a 0.3% fee.
+/// Token D has no pool.
+fn setup() -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the tokens
+    let token_a = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let token_b = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let token_c = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let token_d = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Instantiate the pools
+    let mut pools = Vec::new();
+    for (x, y) in [(token_a, token_b), (token_b, token_c)] {
+        let manifest = ManifestBuilder::new()
+            .withdraw_from_account(account_component, x, dec!("1000"))
+            .withdraw_from_account(account_component, y, dec!("1000"))
+            .take_from_worktop(x, |builder, x_bucket| {
+                builder.take_from_worktop(y, |builder, y_bucket| {
+                    builder.call_function(
+                        package_address,
+                        "Radiswap",
+                        "instantiate_pool",
+                        manifest_args!(
+                            x_bucket,
+                            y_bucket,
+                            dec!("1000"),
+                            "LP",
+                            "LP Token",
+                            "https://example.com/",
+                            dec!("0.003")
+                        ),
+                    )
+                })
+            })
+            .call_method(
+                account_component,
+                "deposit_batch",
+                manifest_args!(ManifestExpression::EntireWorktop),
+            )
+            .build();
+        let receipt = test_runner.execute_manifest_ignoring_fee(
+            manifest,
+            vec![NonFungibleGlobalId::from_public_key(&public_key)],
+        );
+        println!("{:?}\n", receipt);
+        receipt.expect_commit_success();
+        pools.push(receipt.expect_commit(true).new_component_addresses()[0]);
+    }
+
+    // Test the `instantiate_router` function.
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            package_address,
+            "RadiswapRouter",
+            "instantiate_router",
+            manifest_args!(),
+        )
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let router = receipt.expect_commit(true).new_component_addresses()[0];
+    let admin_badge = receipt.expect_commit(true).new_resource_addresses()[0];
+
+    let mut env = TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        router,
+        admin_badge,
+        token_a,
+        token_b,
+        token_c,
+        token_d,
+    };
+
+    // Register both pools
+    for pool in pools {
+        let manifest = ManifestBuilder::new()
+            .create_proof_from_account(account_component, admin_badge)
+            .call_method(router, "register_pool", manifest_args!(pool))
+            .build();
+        execute(&mut env, manifest).expect_commit_success();
+    }
+
+    env
+}
+
+fn execute(env: &mut TestEnv, manifest: TransactionManifest) -> TransactionReceipt {
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Swaps 100 A for OUTPUT through the router.
+fn swap(env: &mut TestEnv, output: ResourceAddress, min_output: Decimal) -> TransactionReceipt {
+    let token_a = env.token_a;
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, token_a, dec!("100"))
+        .take_from_worktop(token_a, |builder, bucket| {
+            builder.call_method(
+                env.router,
+                "swap",
+                manifest_args!(bucket, output, min_output),
+            )
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+#[test]
+fn test_multi_hop_swap() {
+    let mut env = setup();
+    let token_c = env.token_c;
+
+    // 100 A in gives ~90.66 B out of the A/B pool, which give ~82.90 C out of the B/C pool
+    swap(&mut env, token_c, dec!("82.89")).expect_commit_success();
+}
+
+#[test]
+fn test_multi_hop_swap_fails_below_min_output() {
+    let mut env = setup();
+    let token_c = env.token_c;
+
+    swap(&mut env, token_c, dec!("82.9")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_fails_without_path() {
+    let mut env = setup();
+    let token_d = env.token_d;
+
+    swap(&mut env, token_d, dec!("0")).expect_commit_failure();
+}
+
+#[test]
+fn test_deregister_pool_requires_admin_badge() {
+    let mut env = setup();
+    let (admin_badge, token_b, token_c) = (env.admin_badge, env.token_b, env.token_c);
+
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.router,
+            "deregister_pool",
+            manifest_args!(token_b, token_c),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_failure();
+
+    // Once the admin takes the B/C pool out, there is no path from A to C anymore
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, admin_badge)
+        .call_method(
+            env.router,
+            "deregister_pool",
+            manifest_args!(token_b, token_c),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+    swap(&mut env, token_c, dec!("0")).expect_commit_failure();
+}
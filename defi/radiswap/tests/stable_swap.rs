@@ -0,0 +1,259 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    stable_swap: ComponentAddress,
+    token_a: ResourceAddress,
+    token_b: ResourceAddress,
+    lp_token: ResourceAddress,
+    owner_badge: ResourceAddress,
+}
+
+/// Sets up a StableSwap pool with the given reserves and amplification coefficient, and a 0.3% fee.
+fn setup(a_amount: Decimal, b_amount: Decimal, amplification: Decimal) -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the tokens of the pair
+    let token_a = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let token_b = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_pool` function.
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account_component, token_a, a_amount)
+        .withdraw_from_account(account_component, token_b, b_amount)
+        .take_from_worktop(token_a, |builder, a_bucket| {
+            builder.take_from_worktop(token_b, |builder, b_bucket| {
+                builder.call_function(
+                    package_address,
+                    "StableSwap",
+                    "instantiate_pool",
+                    manifest_args!(
+                        a_bucket,
+                        b_bucket,
+                        "LP",
+                        "LP Token",
+                        "https://example.com/",
+                        dec!("0.003"),
+                        amplification
+                    ),
+                )
+            })
+        })
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let stable_swap = receipt.expect_commit(true).new_component_addresses()[0];
+    let lp_token = receipt.expect_commit(true).new_resource_addresses()[1];
+    let owner_badge = receipt.expect_commit(true).new_resource_addresses()[2];
+
+    TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        stable_swap,
+        token_a,
+        token_b,
+        lp_token,
+        owner_badge,
+    }
+}
+
+/// Withdraws LP tokens from the account and deposits them back, which fails unless the account holds them.
+fn hold_lp_tokens(env: &mut TestEnv, amount: Decimal) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.lp_token, amount)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Swaps `input_amount` of `input` and asserts at least `min_output` of `output` is returned.
+fn swap(
+    env: &mut TestEnv,
+    input: ResourceAddress,
+    input_amount: Decimal,
+    output: ResourceAddress,
+    min_output: Decimal,
+) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, input, input_amount)
+        .take_from_worktop(input, |builder, bucket| {
+            builder.call_method(env.stable_swap, "swap", manifest_args!(bucket))
+        })
+        .assert_worktop_contains_by_amount(min_output, output)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+fn ramp_amplification(
+    env: &mut TestEnv,
+    future_amplification: Decimal,
+    end_epoch: u64,
+    with_owner_badge: bool,
+) -> TransactionReceipt {
+    let mut builder = ManifestBuilder::new();
+    if with_owner_badge {
+        builder.create_proof_from_account(env.account_component, env.owner_badge);
+    }
+    let manifest = builder
+        .call_method(
+            env.stable_swap,
+            "ramp_amplification",
+            manifest_args!(future_amplification, end_epoch),
+        )
+        .build();
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+#[test]
+fn test_invariant_of_balanced_pool_is_sum_of_reserves() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("100"));
+
+    // One LP token is minted per unit of D, which is x + y when the pool is balanced
+    hold_lp_tokens(&mut env, dec!("2000")).expect_commit_success();
+    hold_lp_tokens(&mut env, dec!("2000.000000000000000001")).expect_commit_failure();
+}
+
+#[test]
+fn test_invariant_of_imbalanced_pool_converges_below_sum_of_reserves() {
+    let mut env = setup(dec!("1500"), dec!("500"), dec!("100"));
+
+    // 4A(x + y) + D = 4AD + D^3 / (4xy) gives D = ~1998.3457
+    hold_lp_tokens(&mut env, dec!("1998.3457")).expect_commit_success();
+    hold_lp_tokens(&mut env, dec!("1998.3458")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("100"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    // 100 A in, 0.3% fee: ~99.6501 B out, far more than the ~90.66 B of a constant-product pool
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("99.65")).expect_commit_success();
+}
+
+#[test]
+fn test_swap_fails_when_asserting_more_than_output() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("100"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("99.66")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_round_trip_loses_the_fees() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("100"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    // Swapping the ~99.6501 B back returns ~99.4012 A, less than the 100 A put in
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("99.65")).expect_commit_success();
+    swap(&mut env, token_b, dec!("99.65"), token_a, dec!("99.40")).expect_commit_success();
+
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("100"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("99.65")).expect_commit_success();
+    swap(&mut env, token_b, dec!("99.65"), token_a, dec!("100")).expect_commit_failure();
+}
+
+#[test]
+fn test_amplification_ramps_linearly() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("10"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    // Ramp from 10 to 100 between epochs 10 and 20
+    env.test_runner.set_current_epoch(10);
+    ramp_amplification(&mut env, dec!("100"), 20, true).expect_commit_success();
+
+    // Half way, A = 55 gives ~99.6096 B out for 100 A in
+    env.test_runner.set_current_epoch(15);
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("99.60")).expect_commit_success();
+
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("10"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    env.test_runner.set_current_epoch(10);
+    ramp_amplification(&mut env, dec!("100"), 20, true).expect_commit_success();
+    env.test_runner.set_current_epoch(15);
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("99.61")).expect_commit_failure();
+}
+
+#[test]
+fn test_amplification_ramp_ends_at_future_amplification() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("10"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    env.test_runner.set_current_epoch(10);
+    ramp_amplification(&mut env, dec!("100"), 20, true).expect_commit_success();
+
+    // After the ramp, A = 100 gives ~99.6501 B out for 100 A in
+    env.test_runner.set_current_epoch(25);
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("99.65")).expect_commit_success();
+}
+
+#[test]
+fn test_amplification_ramp_fails_beyond_max_change() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("10"));
+
+    env.test_runner.set_current_epoch(10);
+    ramp_amplification(&mut env, dec!("101"), 20, true).expect_commit_failure();
+}
+
+#[test]
+fn test_amplification_ramp_fails_below_min_duration() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("10"));
+
+    env.test_runner.set_current_epoch(10);
+    ramp_amplification(&mut env, dec!("100"), 19, true).expect_commit_failure();
+}
+
+#[test]
+fn test_amplification_ramp_fails_without_owner_badge() {
+    let mut env = setup(dec!("1000"), dec!("1000"), dec!("10"));
+
+    env.test_runner.set_current_epoch(10);
+    ramp_amplification(&mut env, dec!("100"), 20, false).expect_commit_failure();
+}
@@ -0,0 +1,322 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    weighted_pool: ComponentAddress,
+    token_a: ResourceAddress,
+    token_b: ResourceAddress,
+    lp_token: ResourceAddress,
+}
+
+/// Sets up a WeightedPool with 1,000 A and 1,000 B of the given weights, 100 LP tokens and a 0.3% fee.
+fn setup(a_weight: Decimal, b_weight: Decimal) -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the tokens of the pool
+    let token_a = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let token_b = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_pool` function.
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account_component, token_a, dec!("1000"))
+        .withdraw_from_account(account_component, token_b, dec!("1000"))
+        .take_from_worktop(token_a, |builder, a_bucket| {
+            builder.take_from_worktop(token_b, |builder, b_bucket| {
+                builder.call_function(
+                    package_address,
+                    "WeightedPool",
+                    "instantiate_pool",
+                    manifest_args!(
+                        vec![a_bucket, b_bucket],
+                        vec![a_weight, b_weight],
+                        dec!("100"),
+                        "LP",
+                        "LP Token",
+                        "https://example.com/",
+                        dec!("0.003")
+                    ),
+                )
+            })
+        })
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let weighted_pool = receipt.expect_commit(true).new_component_addresses()[0];
+    let lp_token = receipt.expect_commit(true).new_resource_addresses()[1];
+
+    TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        weighted_pool,
+        token_a,
+        token_b,
+        lp_token,
+    }
+}
+
+fn execute(env: &mut TestEnv, manifest: TransactionManifest) -> TransactionReceipt {
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Swaps `input_amount` of `input` for `output` and asserts at least `min_output` is returned.
+fn swap(
+    env: &mut TestEnv,
+    input: ResourceAddress,
+    input_amount: Decimal,
+    output: ResourceAddress,
+    min_output: Decimal,
+) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, input, input_amount)
+        .take_from_worktop(input, |builder, bucket| {
+            builder.call_method(env.weighted_pool, "swap", manifest_args!(bucket, output))
+        })
+        .assert_worktop_contains_by_amount(min_output, output)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+/// Redeems LP tokens into `output` and asserts at least `min_output` is returned.
+fn remove_single_asset_liquidity(
+    env: &mut TestEnv,
+    lp_amount: Decimal,
+    output: ResourceAddress,
+    min_output: Decimal,
+) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, env.lp_token, lp_amount)
+        .take_from_worktop(env.lp_token, |builder, bucket| {
+            builder.call_method(
+                env.weighted_pool,
+                "remove_single_asset_liquidity",
+                manifest_args!(bucket, output),
+            )
+        })
+        .assert_worktop_contains_by_amount(min_output, output)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+#[test]
+fn test_balanced_pool_swaps_like_constant_product() {
+    let mut env = setup(dec!("50"), dec!("50"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    // 100 A in, 0.3% fee: 1000 - 1000 * 1000 / 1099.7 = ~90.6611 B out, as in Radiswap
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("90.66")).expect_commit_success();
+
+    let mut env = setup(dec!("50"), dec!("50"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("90.67")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_follows_weights() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    // 100 B in: 1000 * (1 - (1000 / 1099.7) ^ (0.2 / 0.8)) = ~23.4793 A out
+    swap(&mut env, token_b, dec!("100"), token_a, dec!("23.47")).expect_commit_success();
+
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    swap(&mut env, token_b, dec!("100"), token_a, dec!("23.48")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_round_trip_keeps_invariant() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    // Swapping the ~23.4793 A back returns ~99.4081 B, so the fees stay in the pool
+    swap(&mut env, token_b, dec!("100"), token_a, dec!("23.47")).expect_commit_success();
+    swap(&mut env, token_a, dec!("23.47"), token_b, dec!("99.40")).expect_commit_success();
+
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+    swap(&mut env, token_b, dec!("100"), token_a, dec!("23.47")).expect_commit_success();
+    swap(&mut env, token_a, dec!("23.47"), token_b, dec!("100")).expect_commit_failure();
+}
+
+#[test]
+fn test_swap_fails_above_max_out_ratio() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, token_b) = (env.token_a, env.token_b);
+
+    // 100 A in would take ~316 B out, more than 30% of the reserve
+    swap(&mut env, token_a, dec!("100"), token_b, dec!("0")).expect_commit_failure();
+}
+
+#[test]
+fn test_single_asset_liquidity_round_trip() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, lp_token) = (env.token_a, env.lp_token);
+
+    // 100 A in: 100 * ((1 + 99.94 / 1000) ^ 0.8 - 1) = ~7.9183 LP tokens out
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, token_a, dec!("100"))
+        .take_from_worktop(token_a, |builder, bucket| {
+            builder.call_method(
+                env.weighted_pool,
+                "add_single_asset_liquidity",
+                manifest_args!(bucket),
+            )
+        })
+        .assert_worktop_contains_by_amount(dec!("7.91"), lp_token)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+
+    // Redeeming 7.91 of them returns ~99.7815 A, less than the 100 A put in
+    remove_single_asset_liquidity(&mut env, dec!("7.91"), token_a, dec!("99.78"))
+        .expect_commit_success();
+}
+
+#[test]
+fn test_single_asset_liquidity_round_trip_loses_the_fees() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let token_a = env.token_a;
+
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, token_a, dec!("100"))
+        .take_from_worktop(token_a, |builder, bucket| {
+            builder.call_method(
+                env.weighted_pool,
+                "add_single_asset_liquidity",
+                manifest_args!(bucket),
+            )
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+
+    remove_single_asset_liquidity(&mut env, dec!("7.91"), token_a, dec!("100"))
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_remove_whole_supply_into_single_asset_fails() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let token_a = env.token_a;
+
+    remove_single_asset_liquidity(&mut env, dec!("100"), token_a, dec!("0"))
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_remove_liquidity_returns_whole_reserves() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, token_b, lp_token) = (env.token_a, env.token_b, env.lp_token);
+
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, lp_token, dec!("100"))
+        .take_from_worktop(lp_token, |builder, bucket| {
+            builder.call_method(
+                env.weighted_pool,
+                "remove_liquidity",
+                manifest_args!(bucket),
+            )
+        })
+        .assert_worktop_contains_by_amount(dec!("1000"), token_a)
+        .assert_worktop_contains_by_amount(dec!("1000"), token_b)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+}
+
+#[test]
+fn test_add_liquidity_to_emptied_pool() {
+    let mut env = setup(dec!("80"), dec!("20"));
+    let (token_a, token_b, lp_token) = (env.token_a, env.token_b, env.lp_token);
+
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, lp_token, dec!("100"))
+        .take_from_worktop(lp_token, |builder, bucket| {
+            builder.call_method(
+                env.weighted_pool,
+                "remove_liquidity",
+                manifest_args!(bucket),
+            )
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+
+    // The first deposit into the emptied pool gets the initial 100 LP tokens, whatever the amounts
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, token_a, dec!("400"))
+        .withdraw_from_account(env.account_component, token_b, dec!("100"))
+        .take_from_worktop(token_a, |builder, a_bucket| {
+            builder.take_from_worktop(token_b, |builder, b_bucket| {
+                builder.call_method(
+                    env.weighted_pool,
+                    "add_liquidity",
+                    manifest_args!(vec![a_bucket, b_bucket]),
+                )
+            })
+        })
+        .assert_worktop_contains_by_amount(dec!("100"), lp_token)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+
+    // 80/20 weights on 400 A and 100 B price A at 1 B
+    swap(&mut env, token_a, dec!("1"), token_b, dec!("0.99")).expect_commit_success();
+}
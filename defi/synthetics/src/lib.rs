@@ -3,18 +3,46 @@ use scrypto::prelude::*;
 external_component! {
     PriceOracleComponentTarget {
         fn get_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Option<Decimal>;
-        fn update_price(&self, base: ResourceAddress, quote: ResourceAddress, price: Decimal);
+        fn update_price(&self, reporter: Proof, base: ResourceAddress, quote: ResourceAddress, price: Decimal);
         fn admin_badge_address(&self) -> ResourceAddress;
+        fn get_first_price(&self, base: ResourceAddress, quote: ResourceAddress, epoch: u64) -> Option<Decimal>;
+        fn is_admin_priced(&self, base: ResourceAddress, quote: ResourceAddress) -> bool;
     }
 }
 
-// Main missing features:
-// - Liquidation
-// - Authorization through badge
+/// The length of a reward period, in epochs of about 5 minutes.
+const EPOCHS_PER_WEEK: u64 = 2016;
+
+/// The number of epochs an exchange waits before it is settled at the first prices of that time.
+const SETTLEMENT_DELAY: u64 = 2;
+
+/// The number of epochs after the settlement delay the oracle has to report a price in, before an exchange is
+/// cancelled.
+const SETTLEMENT_WINDOW: u64 = 12;
+
+#[derive(NonFungibleData, ScryptoSbor)]
+pub struct SyntheticsUser {
+    /// The epoch the user registered at
+    pub registered_epoch: u64,
+}
+
+#[derive(NonFungibleData, ScryptoSbor)]
+pub struct ExchangeReceipt {
+    /// The synth exchanged
+    pub source_resource_address: ResourceAddress,
+    /// The amount exchanged, held in escrow until settlement
+    pub amount: Decimal,
+    /// The symbol of the synth to receive
+    pub target_symbol: String,
+    /// The epoch of the exchange
+    pub epoch: u64,
+}
 
 #[blueprint]
 mod synthetic_pool {
     struct SyntheticPool {
+        /// The admin badge resource address
+        admin_badge_address: ResourceAddress,
         /// The price oracle
         oracle_address: ComponentAddress,
         /// The collateralization ratio one has to maintain when minting synthetics
@@ -25,13 +53,51 @@ mod synthetic_pool {
         usd_resource_address: ResourceAddress,
 
         /// Users
-        users: KeyValueStore<ResourceAddress, User>,
+        users: KeyValueStore<NonFungibleLocalId, User>,
+        /// The IDs of all users, in order of registration
+        user_ids: Vec<NonFungibleLocalId>,
+        /// The user badge resource address
+        user_badge_resource_address: ResourceAddress,
+        /// The state of users of the previous version, keyed by the resource of the badge they identified with,
+        /// until they migrate to a user badge
+        legacy_users: KeyValueStore<ResourceAddress, User>,
+        /// The share of the debt retired which a liquidator receives on top, in SNX
+        liquidation_bonus: Decimal,
+        /// The largest share of a user's debt a single liquidation may retire
+        close_factor: Decimal,
         /// Synthetics
-        synthetics: HashMap<String, SyntheticToken>,
+        synthetics: KeyValueStore<String, SyntheticToken>,
+        /// The symbols of all synths, in order of listing
+        synth_symbols: Vec<String>,
+        /// The symbol of the synth of each synth resource
+        synth_symbols_by_resource: KeyValueStore<ResourceAddress, String>,
+        /// The total global debt, as of the last revaluation of each synth.  Minting, burning and liquidating revalue
+        /// the synth they touch, and keepers revalue the others as prices move through `refresh_debt_cache`.
+        total_global_debt: Decimal,
         /// Mint badge
         synthetics_mint_badge: Vault,
         /// Global debt
         synthetics_global_debt_share_resource_address: ResourceAddress,
+        /// The fee charged on minting, in the synth minted
+        mint_fee: Decimal,
+        /// The fee charged on exchanging one synth for another, in the synth received
+        exchange_fee: Decimal,
+        /// The fees collected in each synth, until claimed by stakers
+        fee_vaults: KeyValueStore<ResourceAddress, Vault>,
+        /// The fees collected in each synth during the current reward period
+        pending_fees: HashMap<ResourceAddress, Decimal>,
+        /// The reward units distributed per global debt share, over all closed reward periods. Each unit claims an
+        /// equal part of the fees distributed in every synth, so stakers accrue a single number whatever the number
+        /// of synths.
+        reward_per_share: Decimal,
+        /// The reward units distributed and not yet claimed
+        total_reward_units: Decimal,
+        /// The epoch the current reward period started at
+        period_start_epoch: u64,
+        /// The synths held for exchanges waiting for settlement
+        exchange_escrow: KeyValueStore<ResourceAddress, Vault>,
+        /// The exchange receipt resource address
+        exchange_receipt_resource_address: ResourceAddress,
     }
 
     impl SyntheticPool {
@@ -40,7 +106,11 @@ mod synthetic_pool {
             snx_token_address: ResourceAddress,
             usd_token_address: ResourceAddress,
             collateralization_threshold: Decimal,
-        ) -> ComponentAddress {
+        ) -> (ComponentAddress, Bucket) {
+            let admin_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "Synthetic Pool Admin Badge")
+                .mint_initial_supply(1);
             let synthetics_mint_badge = ResourceBuilder::new_fungible()
                 .divisibility(DIVISIBILITY_NONE)
                 .metadata("name", "Synthetics Mint Badge")
@@ -57,19 +127,80 @@ mod synthetic_pool {
                     LOCKED,
                 )
                 .create_with_no_initial_supply();
+            let user_badge_resource_address =
+                ResourceBuilder::new_uuid_non_fungible::<SyntheticsUser>()
+                    .metadata("name", "Synthetic Pool User Badge")
+                    .mintable(
+                        rule!(require(synthetics_mint_badge.resource_address())),
+                        LOCKED,
+                    )
+                    .create_with_no_initial_supply();
+            let exchange_receipt_resource_address =
+                ResourceBuilder::new_uuid_non_fungible::<ExchangeReceipt>()
+                    .metadata("name", "Synthetic Pool Exchange Receipt")
+                    .mintable(
+                        rule!(require(synthetics_mint_badge.resource_address())),
+                        LOCKED,
+                    )
+                    .burnable(
+                        rule!(require(synthetics_mint_badge.resource_address())),
+                        LOCKED,
+                    )
+                    .create_with_no_initial_supply();
+
+            let mut rules = AccessRulesConfig::new();
+            for method in [
+                "add_synthetic_token",
+                "set_mint_cap",
+                "pause_synthetic_token",
+                "unpause_synthetic_token",
+                "delist_synthetic_token",
+                "set_liquidation_bonus",
+                "set_close_factor",
+                "set_mint_fee",
+                "set_exchange_fee",
+                "import_legacy_user",
+            ] {
+                rules = rules.method(
+                    method,
+                    rule!(require(admin_badge.resource_address())),
+                    AccessRule::DenyAll,
+                );
+            }
+            let rules = rules.default(rule!(allow_all), AccessRule::DenyAll);
 
-            Self {
+            let component = Self {
+                admin_badge_address: admin_badge.resource_address(),
                 oracle_address,
                 collateralization_threshold,
                 snx_resource_address: snx_token_address,
                 usd_resource_address: usd_token_address,
                 users: KeyValueStore::new(),
-                synthetics: HashMap::new(),
+                user_ids: Vec::new(),
+                user_badge_resource_address,
+                legacy_users: KeyValueStore::new(),
+                liquidation_bonus: dec!("0.1"),
+                close_factor: dec!("0.5"),
+                synthetics: KeyValueStore::new(),
+                synth_symbols: Vec::new(),
+                synth_symbols_by_resource: KeyValueStore::new(),
+                total_global_debt: Decimal::zero(),
                 synthetics_mint_badge: Vault::with_bucket(synthetics_mint_badge),
                 synthetics_global_debt_share_resource_address,
+                mint_fee: dec!("0.003"),
+                exchange_fee: dec!("0.003"),
+                fee_vaults: KeyValueStore::new(),
+                pending_fees: HashMap::new(),
+                reward_per_share: Decimal::zero(),
+                total_reward_units: Decimal::zero(),
+                period_start_epoch: Runtime::current_epoch(),
+                exchange_escrow: KeyValueStore::new(),
+                exchange_receipt_resource_address,
             }
             .instantiate()
-            .globalize()
+            .globalize_with_access_rules(rules);
+
+            (component, admin_badge)
         }
 
         /// Add new a new synthetic token to the protocol
@@ -79,7 +210,7 @@ mod synthetic_pool {
             asset_address: ResourceAddress,
         ) -> ResourceAddress {
             assert!(
-                !self.synthetics.contains_key(&asset_symbol),
+                self.synthetics.get(&asset_symbol).is_none(),
                 "Asset already exist",
             );
 
@@ -97,6 +228,13 @@ mod synthetic_pool {
                 )
                 .create_with_no_initial_supply();
 
+            self.fee_vaults
+                .insert(token_resource_address, Vault::new(token_resource_address));
+            self.exchange_escrow
+                .insert(token_resource_address, Vault::new(token_resource_address));
+            self.synth_symbols_by_resource
+                .insert(token_resource_address, asset_symbol.clone());
+            self.synth_symbols.push(asset_symbol.clone());
             self.synthetics.insert(
                 asset_symbol.clone(),
                 SyntheticToken::new(asset_symbol, asset_address, token_resource_address.clone()),
@@ -105,36 +243,120 @@ mod synthetic_pool {
             token_resource_address
         }
 
+        /// Caps the supply of a synth which may be minted, or removes the cap with `None`.
+        pub fn set_mint_cap(&mut self, asset_symbol: String, mint_cap: Option<Decimal>) {
+            assert!(
+                mint_cap.map_or(true, |cap| !cap.is_negative()),
+                "Mint cap can't be negative"
+            );
+            self.get_synth_mut(&asset_symbol).mint_cap = mint_cap;
+        }
+
+        /// Pauses minting and exchanging of a synth. Burning and liquidating with it are still possible.
+        pub fn pause_synthetic_token(&mut self, asset_symbol: String) {
+            self.get_synth_mut(&asset_symbol).paused = true;
+        }
+
+        /// Resumes minting and exchanging of a synth.
+        pub fn unpause_synthetic_token(&mut self, asset_symbol: String) {
+            self.get_synth_mut(&asset_symbol).paused = false;
+        }
+
+        /// Delists a synth, freezing its price at the current oracle price for good. It can no longer be minted or
+        /// exchanged.  Holders burn it against their debt at that price through `redeem_delisted`, or exchange it for
+        /// listed synths through `exchange_delisted`.
+        pub fn delist_synthetic_token(&mut self, asset_symbol: String) {
+            let asset_address = self
+                .synthetics
+                .get(&asset_symbol)
+                .expect("Unknown synth")
+                .asset_address;
+            let final_price = self.get_asset_price(asset_address);
+            let mut synth = self.get_synth_mut(&asset_symbol);
+            assert!(synth.delisted_price.is_none(), "Synth already delisted");
+            synth.delisted_price = Some(final_price);
+            drop(synth);
+            self.revalue_synth(&asset_symbol, final_price);
+        }
+
+        /// Sets the share of the debt retired which a liquidator receives on top, in SNX.
+        pub fn set_liquidation_bonus(&mut self, liquidation_bonus: Decimal) {
+            assert!(
+                liquidation_bonus >= Decimal::zero() && liquidation_bonus <= dec!("1"),
+                "Invalid liquidation bonus"
+            );
+            self.liquidation_bonus = liquidation_bonus;
+        }
+
+        /// Sets the largest share of a user's debt a single liquidation may retire, from 0 (excluded) to 1.
+        pub fn set_close_factor(&mut self, close_factor: Decimal) {
+            assert!(
+                close_factor > Decimal::zero() && close_factor <= dec!("1"),
+                "Invalid close factor"
+            );
+            self.close_factor = close_factor;
+        }
+
+        /// Sets the fee charged on minting, from 0 to 1.
+        pub fn set_mint_fee(&mut self, mint_fee: Decimal) {
+            assert!(
+                mint_fee >= Decimal::zero() && mint_fee < dec!("1"),
+                "Invalid mint fee"
+            );
+            self.mint_fee = mint_fee;
+        }
+
+        /// Sets the fee charged on exchanging one synth for another, from 0 to 1.
+        pub fn set_exchange_fee(&mut self, exchange_fee: Decimal) {
+            assert!(
+                exchange_fee >= Decimal::zero() && exchange_fee < dec!("1"),
+                "Invalid exchange fee"
+            );
+            self.exchange_fee = exchange_fee;
+        }
+
         /// Deposits SNX into my staking account
         pub fn stake(&mut self, user_auth: Proof, stake_in_snx: Bucket) {
-            let user_id = Self::get_user_id(user_auth);
+            let user_id = self.get_user_id(user_auth);
             let mut user = self.get_user(user_id, true);
             user.snx.put(stake_in_snx);
         }
 
         /// Withdraws SNX from my staking account.
         pub fn unstake(&mut self, user_auth: Proof, amount: Decimal) -> Bucket {
-            let user_id = Self::get_user_id(user_auth);
+            let user_id = self.get_user_id(user_auth);
             let mut user = self.get_user(user_id, false);
 
             let tokens = user.snx.take(amount);
             user.check_collateralization_ratio(
                 self.get_snx_price(),
-                self.get_total_global_debt(),
+                self.total_global_debt,
                 self.synthetics_global_debt_share_resource_address.clone(),
                 self.collateralization_threshold,
             );
             tokens
         }
 
-        /// Mints synthetics tokens
+        /// Mints synthetics tokens, less the mint fee
         pub fn mint(&mut self, user_auth: Proof, amount: Decimal, symbol: String) -> Bucket {
-            let user_id = Self::get_user_id(user_auth);
+            let user_id = self.get_user_id(user_auth);
             let mut user = self.get_user(user_id, false);
+            user.accrue_rewards(self.reward_per_share);
 
             let synth = self.synthetics.get(&symbol).unwrap().clone();
-            let global_debt = self.get_total_global_debt();
-            let new_debt = self.get_asset_price(synth.asset_address) * amount;
+            synth.assert_tradable();
+            if let Some(mint_cap) = synth.mint_cap {
+                assert!(
+                    borrow_resource_manager!(synth.token_resource_address).total_supply() + amount
+                        <= mint_cap,
+                    "Mint cap of s{} reached",
+                    symbol
+                );
+            }
+            let price = self.get_synth_price(&synth);
+            self.revalue_synth(&symbol, price);
+            let global_debt = self.total_global_debt;
+            let new_debt = price * amount;
 
             user.global_debt_share
                 .put(self.synthetics_mint_badge.authorize(|| {
@@ -149,13 +371,15 @@ mod synthetic_pool {
                                 / synthetics_global_debt_share_resource_manager.total_supply())
                     })
                 }));
-            let tokens = self.synthetics_mint_badge.authorize(|| {
+            let mut tokens = self.synthetics_mint_badge.authorize(|| {
                 let token_resource_manager = borrow_resource_manager!(synth.token_resource_address);
                 token_resource_manager.mint(amount)
             });
+            self.revalue_synth(&symbol, price);
+            self.collect_fee(tokens.take(amount * self.mint_fee));
             user.check_collateralization_ratio(
                 self.get_snx_price(),
-                self.get_total_global_debt(),
+                self.total_global_debt,
                 self.synthetics_global_debt_share_resource_address.clone(),
                 self.collateralization_threshold,
             );
@@ -164,17 +388,15 @@ mod synthetic_pool {
 
         /// Burns synthetic tokens
         pub fn burn(&mut self, user_auth: Proof, bucket: Bucket) {
-            let user_id = Self::get_user_id(user_auth);
+            let user_id = self.get_user_id(user_auth);
             let mut user = self.get_user(user_id, false);
+            user.accrue_rewards(self.reward_per_share);
 
-            let synth = self
-                .synthetics
-                .iter()
-                .find(|(_, v)| v.token_resource_address == bucket.resource_address())
-                .unwrap()
-                .1;
-            let global_debt = self.get_total_global_debt();
-            let debt_to_remove = self.get_asset_price(synth.asset_address) * bucket.amount();
+            let synth = self.get_synth_by_resource(bucket.resource_address());
+            let price = self.get_synth_price(&synth);
+            self.revalue_synth(&synth.asset_symbol, price);
+            let global_debt = self.total_global_debt;
+            let debt_to_remove = price * bucket.amount();
             let shares_to_burn = user.global_debt_share.take(
                 borrow_resource_manager!(self.synthetics_global_debt_share_resource_address)
                     .total_supply()
@@ -188,16 +410,337 @@ mod synthetic_pool {
             self.synthetics_mint_badge.authorize(|| {
                 bucket.burn();
             });
+            self.revalue_synth(&synth.asset_symbol, price);
+        }
+
+        /// Burns synthetic tokens to retire debt of a user below the collateralization threshold, and returns the
+        /// user's SNX worth the debt retired plus the liquidation bonus. At most the close factor of the user's debt
+        /// may be retired at once.
+        pub fn liquidate(&mut self, user_id: NonFungibleLocalId, synth_bucket: Bucket) -> Bucket {
+            let snx_price = self.get_snx_price();
+            let synth = self.get_synth_by_resource(synth_bucket.resource_address());
+            let price = self.get_synth_price(&synth);
+            self.revalue_synth(&synth.asset_symbol, price);
+            let global_debt = self.total_global_debt;
+            let mut user = self.get_user(user_id, false);
+            assert!(
+                user.is_under_collateralized(
+                    snx_price,
+                    global_debt,
+                    self.synthetics_global_debt_share_resource_address,
+                    self.collateralization_threshold,
+                ),
+                "User is not under collateralized"
+            );
+            user.accrue_rewards(self.reward_per_share);
+
+            // Retire the share of the debt the synths are worth, up to the close factor
+            let total_shares =
+                borrow_resource_manager!(self.synthetics_global_debt_share_resource_address)
+                    .total_supply();
+            let user_debt = global_debt / total_shares * user.global_debt_share.amount();
+            let debt_to_remove = price * synth_bucket.amount();
+            assert!(
+                debt_to_remove <= user_debt * self.close_factor,
+                "Liquidation exceeds {} of the debt of the user",
+                self.close_factor
+            );
+            let shares_to_burn = total_shares * debt_to_remove / global_debt;
+            assert!(
+                shares_to_burn <= user.global_debt_share.amount(),
+                "Liquidation exceeds the debt of the user"
+            );
+            let shares_to_burn = user.global_debt_share.take(shares_to_burn);
+            self.synthetics_mint_badge.authorize(|| {
+                shares_to_burn.burn();
+            });
+            self.synthetics_mint_badge.authorize(|| {
+                synth_bucket.burn();
+            });
+            self.revalue_synth(&synth.asset_symbol, price);
+
+            // Seize the SNX worth the debt retired plus the bonus, or all of it if there isn't enough
+            let mut snx_to_seize =
+                debt_to_remove * (dec!("1") + self.liquidation_bonus) / snx_price;
+            if snx_to_seize > user.snx.amount() {
+                snx_to_seize = user.snx.amount();
+            }
+            user.snx.take(snx_to_seize)
+        }
+
+        /// Returns the IDs of the users below the collateralization threshold, among at most `limit` users starting
+        /// at `offset` in order of registration. Keepers page through all users with `get_user_count`.
+        pub fn get_liquidatable_users(&self, offset: u64, limit: u64) -> Vec<NonFungibleLocalId> {
+            let snx_price = self.get_snx_price();
+            let global_debt = self.total_global_debt;
+            self.user_ids
+                .iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .filter(|user_id| {
+                    self.users.get(user_id).unwrap().is_under_collateralized(
+                        snx_price,
+                        global_debt,
+                        self.synthetics_global_debt_share_resource_address,
+                        self.collateralization_threshold,
+                    )
+                })
+                .cloned()
+                .collect()
+        }
+
+        /// Returns the number of users who have staked, for paging through `get_liquidatable_users`.
+        pub fn get_user_count(&self) -> u64 {
+            self.user_ids.len() as u64
+        }
+
+        /// Returns the share of the debt retired which a liquidator receives on top, in SNX.
+        pub fn get_liquidation_bonus(&self) -> Decimal {
+            self.liquidation_bonus
+        }
+
+        /// Returns the largest share of a user's debt a single liquidation may retire.
+        pub fn get_close_factor(&self) -> Decimal {
+            self.close_factor
+        }
+
+        /// Exchanges synthetic tokens for another synth of the same value, less the exchange fee, returning a receipt
+        /// for the exchange.  The tokens are held until the receipt is settled through `settle_exchange`, and valued at
+        /// the first oracle prices reported at least `SETTLEMENT_DELAY` epochs later, so that pending oracle updates
+        /// can't be front-run and the time of settlement makes no difference.  Exchanging leaves the global debt
+        /// unchanged, so any user may exchange, staker or not.  Both synths must be priced by the oracle's reporters
+        /// directly, as only their reports are kept as settlement prices.
+        pub fn exchange(
+            &mut self,
+            user_auth: Proof,
+            bucket: Bucket,
+            target_symbol: String,
+        ) -> Bucket {
+            self.get_user_id(user_auth);
+            let source = self.get_synth_by_resource(bucket.resource_address());
+            source.assert_tradable();
+            self.assert_settleable(&source);
+            self.open_exchange(bucket, target_symbol)
+        }
+
+        /// Redeems delisted synths at their final price, burning them to retire as much of my debt as they are worth,
+        /// like `burn` does.
+        pub fn redeem_delisted(&mut self, user_auth: Proof, bucket: Bucket) {
+            self.assert_delisted(bucket.resource_address());
+            self.burn(user_auth, bucket);
+        }
+
+        /// Exchanges delisted synths for a listed synth of the same value at their final price, less the exchange
+        /// fee, through a receipt settled like that of an exchange.  Holders without debt to retire exchange them
+        /// this way instead of redeeming them.
+        pub fn exchange_delisted(
+            &mut self,
+            user_auth: Proof,
+            bucket: Bucket,
+            target_symbol: String,
+        ) -> Bucket {
+            self.get_user_id(user_auth);
+            self.assert_delisted(bucket.resource_address());
+            self.open_exchange(bucket, target_symbol)
+        }
+
+        /// Settles an exchange, burning its receipt and returning the synths received.  An exchange from a synth
+        /// delisted in the meantime is settled at its final price.  An exchange involving a synth otherwise paused or
+        /// delisted in the meantime is cancelled, returning the synths exchanged, and so is one the oracle has
+        /// reported no price for within `SETTLEMENT_WINDOW` epochs after the settlement delay.
+        pub fn settle_exchange(&mut self, receipt: Bucket) -> Bucket {
+            assert!(
+                receipt.resource_address() == self.exchange_receipt_resource_address,
+                "Invalid exchange receipt"
+            );
+            let exchange: ExchangeReceipt = receipt.non_fungible().data();
+            let settlement_epoch = exchange.epoch + SETTLEMENT_DELAY;
+            let current_epoch = Runtime::current_epoch();
+            assert!(
+                current_epoch >= settlement_epoch,
+                "The exchange settles from epoch {}",
+                settlement_epoch
+            );
+
+            let source = self.get_synth_by_resource(exchange.source_resource_address);
+            let target = self
+                .synthetics
+                .get(&exchange.target_symbol)
+                .unwrap()
+                .clone();
+            let prices = if (source.is_tradable() || source.delisted_price.is_some())
+                && target.is_tradable()
+            {
+                // Delisted synths are settled at their final price
+                let source_price = match source.delisted_price {
+                    Some(final_price) => Some(final_price),
+                    None => self.get_settlement_price(&source, settlement_epoch),
+                };
+                match (
+                    source_price,
+                    self.get_settlement_price(&target, settlement_epoch),
+                ) {
+                    (Some(source_price), Some(target_price)) => Some((source_price, target_price)),
+                    _ => {
+                        assert!(
+                            current_epoch > settlement_epoch + SETTLEMENT_WINDOW,
+                            "No settlement price yet, the exchange is cancelled after epoch {}",
+                            settlement_epoch + SETTLEMENT_WINDOW
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            let source_tokens = self
+                .exchange_escrow
+                .get_mut(&exchange.source_resource_address)
+                .unwrap()
+                .take(exchange.amount);
+            self.synthetics_mint_badge.authorize(|| {
+                receipt.burn();
+            });
+            let (source_price, target_price) = match prices {
+                Some(prices) => prices,
+                None => return source_tokens,
+            };
+
+            // Burn the source synths and mint the same value of the target at the settlement prices
+            let target_amount = source_price * source_tokens.amount() / target_price;
+            self.synthetics_mint_badge.authorize(|| {
+                source_tokens.burn();
+            });
+            let mut target_tokens = self.synthetics_mint_badge.authorize(|| {
+                borrow_resource_manager!(target.token_resource_address).mint(target_amount)
+            });
+
+            // The global debt follows the current prices, as for any other change of supply
+            for synth in [&source, &target] {
+                let price = self.get_synth_price(synth);
+                self.revalue_synth(&synth.asset_symbol, price);
+            }
+            self.collect_fee(target_tokens.take(target_amount * self.exchange_fee));
+            target_tokens
+        }
+
+        /// Closes the current reward period once it has lasted a week, distributing the fees collected during it
+        /// to stakers in proportion to their global debt share.
+        pub fn close_period(&mut self) {
+            let current_epoch = Runtime::current_epoch();
+            assert!(
+                current_epoch >= self.period_start_epoch + EPOCHS_PER_WEEK,
+                "The reward period ends at epoch {}",
+                self.period_start_epoch + EPOCHS_PER_WEEK
+            );
+
+            // Without debt, the fees roll over to the next period
+            let total_shares =
+                borrow_resource_manager!(self.synthetics_global_debt_share_resource_address)
+                    .total_supply();
+            if !total_shares.is_zero() && !self.pending_fees.is_empty() {
+                // Issue units in proportion to the value of the fees of the period against that of the fees
+                // distributed before, so that every unit keeps claiming an equal part of all of them
+                let period_value = self
+                    .pending_fees
+                    .iter()
+                    .map(|(token_resource_address, fees)| {
+                        self.get_fee_value(*token_resource_address, *fees)
+                    })
+                    .fold(Decimal::zero(), |total, value| total + value);
+                let distributed_value = self
+                    .get_distributed_fees()
+                    .into_iter()
+                    .map(|(token_resource_address, fees)| {
+                        self.get_fee_value(token_resource_address, fees)
+                    })
+                    .fold(Decimal::zero(), |total, value| total + value);
+                let units = if self.total_reward_units.is_zero() || distributed_value.is_zero() {
+                    period_value
+                } else {
+                    self.total_reward_units * period_value / distributed_value
+                };
+
+                self.reward_per_share += units / total_shares;
+                self.total_reward_units += units;
+                self.pending_fees.clear();
+            }
+            self.period_start_epoch = current_epoch;
+        }
+
+        /// Claims my share of the fees distributed in closed reward periods, one bucket per synth.
+        pub fn claim(&mut self, user_auth: Proof) -> Vec<Bucket> {
+            let user_id = self.get_user_id(user_auth);
+            let mut user = self.get_user(user_id, false);
+            user.accrue_rewards(self.reward_per_share);
+            let units = user.reward_units;
+            user.reward_units = Decimal::zero();
+            if units.is_zero() {
+                return Vec::new();
+            }
+
+            let share = units / self.total_reward_units;
+            self.total_reward_units -= units;
+            let mut rewards = Vec::new();
+            for (token_resource_address, fees) in self.get_distributed_fees() {
+                rewards.push(
+                    self.fee_vaults
+                        .get_mut(&token_resource_address)
+                        .unwrap()
+                        .take(fees * share),
+                );
+            }
+            rewards
         }
 
-        /// Returns the total global debt.
+        /// Returns the fees I can claim in each synth.
+        pub fn get_claimable_rewards(
+            &self,
+            user_id: NonFungibleLocalId,
+        ) -> HashMap<ResourceAddress, Decimal> {
+            let user = self.users.get(&user_id).expect("User not found");
+            let units = user.reward_units
+                + user.global_debt_share.amount()
+                    * (self.reward_per_share - user.reward_per_share_paid);
+            if units.is_zero() {
+                return HashMap::new();
+            }
+
+            let share = units / self.total_reward_units;
+            self.get_distributed_fees()
+                .into_iter()
+                .map(|(token_resource_address, fees)| (token_resource_address, fees * share))
+                .collect()
+        }
+
+        /// Returns the epoch the current reward period ends at.
+        pub fn get_period_end_epoch(&self) -> u64 {
+            self.period_start_epoch + EPOCHS_PER_WEEK
+        }
+
+        /// Returns the total global debt, valuing each synth at its price as of its last revaluation.
         pub fn get_total_global_debt(&self) -> Decimal {
-            let mut total = Decimal::zero();
-            for (_, synth) in &self.synthetics {
-                total += self.get_asset_price(synth.asset_address)
-                    * borrow_resource_manager!(synth.token_resource_address).total_supply();
+            self.total_global_debt
+        }
+
+        /// Revalues synths at their current prices.  Anyone may call this, and keepers do as prices move, as collateral
+        /// checks only revalue the synth they touch.
+        pub fn refresh_debt_cache(&mut self, asset_symbols: Vec<String>) {
+            for asset_symbol in asset_symbols {
+                let synth = self
+                    .synthetics
+                    .get(&asset_symbol)
+                    .expect("Unknown synth")
+                    .clone();
+                let price = self.get_synth_price(&synth);
+                self.revalue_synth(&asset_symbol, price);
             }
-            total
+        }
+
+        /// Returns the symbols of all synths.
+        pub fn get_synthetic_symbols(&self) -> Vec<String> {
+            self.synth_symbols.clone()
         }
 
         /// Retrieves the price of pair SNX/USD
@@ -219,48 +762,290 @@ mod synthetic_pool {
         }
 
         /// Retrieves user summary.
-        pub fn get_user_summary(&mut self, user_id: ResourceAddress) -> String {
+        pub fn get_user_summary(&mut self, user_id: NonFungibleLocalId) -> String {
             let user = self.get_user(user_id, false);
             format!(
                 "SNX balance: {}, SNX price: {}, Debt: {} * {} / {}",
                 user.snx.amount(),
                 self.get_snx_price(),
-                self.get_total_global_debt(),
+                self.total_global_debt,
                 user.global_debt_share.amount(),
                 borrow_resource_manager!(self.synthetics_global_debt_share_resource_address)
                     .total_supply()
             )
         }
 
-        /// Registers a new user
+        /// Registers a new user, returning the badge which identifies them
         pub fn new_user(&self) -> Bucket {
-            ResourceBuilder::new_fungible()
-                .divisibility(DIVISIBILITY_NONE)
-                .metadata("name", "Synthetic Pool User Badge")
-                .mint_initial_supply(1)
+            self.synthetics_mint_badge.authorize(|| {
+                borrow_resource_manager!(self.user_badge_resource_address).mint_uuid_non_fungible(
+                    SyntheticsUser {
+                        registered_epoch: Runtime::current_epoch(),
+                    },
+                )
+            })
+        }
+
+        /// Records the stake and global debt share of a user of the previous version, who identified with any badge
+        /// of LEGACY_BADGE, so that they can take them over through `migrate_user`.
+        pub fn import_legacy_user(
+            &mut self,
+            legacy_badge: ResourceAddress,
+            stake_in_snx: Bucket,
+            global_debt_share: Decimal,
+        ) {
+            assert!(
+                self.legacy_users.get(&legacy_badge).is_none(),
+                "Legacy user already imported"
+            );
+
+            let mut user = User::new(
+                self.snx_resource_address,
+                self.synthetics_global_debt_share_resource_address,
+            );
+            user.reward_per_share_paid = self.reward_per_share;
+            user.snx.put(stake_in_snx);
+            user.global_debt_share
+                .put(self.synthetics_mint_badge.authorize(|| {
+                    borrow_resource_manager!(self.synthetics_global_debt_share_resource_address)
+                        .mint(global_debt_share)
+                }));
+            self.legacy_users.insert(legacy_badge, user);
+        }
+
+        /// Migrates a user of the previous version, identified by a badge they present, to a new user badge carrying
+        /// their stake, global debt share and rewards. Each legacy user may migrate once.
+        pub fn migrate_user(&mut self, legacy_auth: Proof) -> Bucket {
+            let legacy_badge = legacy_auth.resource_address();
+            legacy_auth
+                .validate_proof(ProofValidationMode::ValidateContainsAmount(
+                    legacy_badge,
+                    dec!("1"),
+                ))
+                .expect("Invalid legacy badge");
+            let mut legacy_user = self
+                .legacy_users
+                .get_mut(&legacy_badge)
+                .expect("No legacy user for this badge");
+            assert!(
+                !legacy_user.snx.is_empty() || !legacy_user.global_debt_share.is_empty(),
+                "Legacy user already migrated"
+            );
+            legacy_user.accrue_rewards(self.reward_per_share);
+
+            let user_badge = self.new_user();
+            let mut user = self.get_user(user_badge.non_fungible_local_id(), true);
+            user.reward_per_share_paid = self.reward_per_share;
+            user.reward_units = legacy_user.reward_units;
+            legacy_user.reward_units = Decimal::zero();
+            user.snx.put(legacy_user.snx.take_all());
+            user.global_debt_share
+                .put(legacy_user.global_debt_share.take_all());
+            user_badge
+        }
+
+        /// Returns a synth and its listing status.
+        pub fn get_synthetic_token(&self, asset_symbol: String) -> SyntheticToken {
+            self.synthetics
+                .get(&asset_symbol)
+                .expect("Unknown synth")
+                .clone()
+        }
+
+        /// Returns the admin badge resource address.
+        pub fn admin_badge_address(&self) -> ResourceAddress {
+            self.admin_badge_address
+        }
+
+        /// Returns the user badge resource address.
+        pub fn user_badge_address(&self) -> ResourceAddress {
+            self.user_badge_resource_address
+        }
+
+        /// Returns the exchange receipt resource address.
+        pub fn exchange_receipt_address(&self) -> ResourceAddress {
+            self.exchange_receipt_resource_address
+        }
+
+        /// Retrieves the synth of a resource.
+        fn get_synth_by_resource(&self, token_resource_address: ResourceAddress) -> SyntheticToken {
+            let asset_symbol = self
+                .synth_symbols_by_resource
+                .get(&token_resource_address)
+                .expect("Wrong token type passed in")
+                .clone();
+            self.synthetics.get(&asset_symbol).unwrap().clone()
+        }
+
+        /// Checks the synth of a resource is delisted.
+        fn assert_delisted(&self, token_resource_address: ResourceAddress) {
+            assert!(
+                self.get_synth_by_resource(token_resource_address)
+                    .delisted_price
+                    .is_some(),
+                "Synth is not delisted"
+            );
+        }
+
+        /// Retrieves the price of a synth in USD, which is frozen once it is delisted
+        fn get_synth_price(&self, synth: &SyntheticToken) -> Decimal {
+            match synth.delisted_price {
+                Some(final_price) => final_price,
+                None => self.get_asset_price(synth.asset_address),
+            }
+        }
+
+        /// Holds synths in escrow for an exchange into another synth, returning the receipt for it.
+        fn open_exchange(&mut self, bucket: Bucket, target_symbol: String) -> Bucket {
+            let source = self.get_synth_by_resource(bucket.resource_address());
+            self.synthetics
+                .get(&target_symbol)
+                .expect("Unknown synth")
+                .assert_tradable();
+            assert!(
+                source.asset_symbol != target_symbol,
+                "Can't exchange a synth for itself"
+            );
+            let target = self.synthetics.get(&target_symbol).unwrap().clone();
+            self.assert_settleable(&target);
+
+            let receipt = self.synthetics_mint_badge.authorize(|| {
+                borrow_resource_manager!(self.exchange_receipt_resource_address)
+                    .mint_uuid_non_fungible(ExchangeReceipt {
+                        source_resource_address: bucket.resource_address(),
+                        amount: bucket.amount(),
+                        target_symbol,
+                        epoch: Runtime::current_epoch(),
+                    })
+            });
+            self.exchange_escrow
+                .get_mut(&bucket.resource_address())
+                .unwrap()
+                .put(bucket);
+            receipt
+        }
+
+        /// Checks the oracle records settlement prices for a synth, which it doesn't for prices derived through a hub
+        /// asset or taken from another source.
+        fn assert_settleable(&self, synth: &SyntheticToken) {
+            let oracle: PriceOracleComponentTarget = self.oracle_address.into();
+            assert!(
+                oracle.is_admin_priced(synth.asset_address, self.usd_resource_address),
+                "s{} is not priced by the oracle's reporters, so it can't be exchanged",
+                synth.asset_symbol
+            );
+        }
+
+        /// Retrieves the first price of a synth in USD the oracle reported from an epoch on, within the settlement
+        /// window.  It never changes once reported, whenever the exchange is settled.
+        fn get_settlement_price(&self, synth: &SyntheticToken, epoch: u64) -> Option<Decimal> {
+            let oracle: PriceOracleComponentTarget = self.oracle_address.into();
+            let last_epoch = Runtime::current_epoch().min(epoch + SETTLEMENT_WINDOW);
+            (epoch..=last_epoch).find_map(|epoch| {
+                oracle.get_first_price(synth.asset_address, self.usd_resource_address, epoch)
+            })
+        }
+
+        /// Retrieves a synth for updating its listing.
+        fn get_synth_mut(
+            &mut self,
+            asset_symbol: &String,
+        ) -> scrypto::runtime::DataRefMut<SyntheticToken> {
+            self.synthetics
+                .get_mut(asset_symbol)
+                .expect("Unknown synth")
+        }
+
+        /// Values the supply of a synth at a price, replacing its previous value in the global debt.
+        fn revalue_synth(&mut self, asset_symbol: &String, price: Decimal) {
+            let mut synth = self.get_synth_mut(asset_symbol);
+            let debt =
+                price * borrow_resource_manager!(synth.token_resource_address).total_supply();
+            self.total_global_debt = self.total_global_debt - synth.debt + debt;
+            synth.debt = debt;
+            synth.last_revalued_epoch = Runtime::current_epoch();
+        }
+
+        /// Returns the fees distributed in closed reward periods and not yet claimed, for each synth which has any.
+        fn get_distributed_fees(&self) -> Vec<(ResourceAddress, Decimal)> {
+            self.synth_symbols
+                .iter()
+                .filter_map(|asset_symbol| {
+                    let token_resource_address = self
+                        .synthetics
+                        .get(asset_symbol)
+                        .unwrap()
+                        .token_resource_address;
+                    let pending = self
+                        .pending_fees
+                        .get(&token_resource_address)
+                        .cloned()
+                        .unwrap_or(Decimal::zero());
+                    let fees = self
+                        .fee_vaults
+                        .get(&token_resource_address)
+                        .unwrap()
+                        .amount()
+                        - pending;
+                    if fees.is_zero() {
+                        None
+                    } else {
+                        Some((token_resource_address, fees))
+                    }
+                })
+                .collect()
+        }
+
+        /// Values an amount of fees collected in a synth, in USD.
+        fn get_fee_value(
+            &self,
+            token_resource_address: ResourceAddress,
+            amount: Decimal,
+        ) -> Decimal {
+            let synth = self.get_synth_by_resource(token_resource_address);
+            self.get_synth_price(&synth) * amount
+        }
+
+        /// Puts a fee aside for stakers, to be distributed when the current reward period closes.
+        fn collect_fee(&mut self, fee: Bucket) {
+            *self
+                .pending_fees
+                .entry(fee.resource_address())
+                .or_insert(Decimal::zero()) += fee.amount();
+            self.fee_vaults
+                .get_mut(&fee.resource_address())
+                .unwrap()
+                .put(fee);
         }
 
         /// Parse user id from proof.
-        fn get_user_id(user_auth: Proof) -> ResourceAddress {
-            user_auth.unsafe_skip_proof_validation().resource_address()
+        fn get_user_id(&self, user_auth: Proof) -> NonFungibleLocalId {
+            user_auth
+                .validate_proof(ProofValidationMode::ValidateContainsAmount(
+                    self.user_badge_resource_address,
+                    dec!("1"),
+                ))
+                .expect("Invalid user badge")
+                .non_fungible_local_id()
         }
 
         /// Retrieves user state.
         fn get_user(
             &mut self,
-            user_id: ResourceAddress,
+            user_id: NonFungibleLocalId,
             create_if_missing: bool,
         ) -> scrypto::runtime::DataRefMut<User> {
             if let Some(user) = self.users.get_mut(&user_id) {
                 user
             } else if create_if_missing {
                 self.users.insert(
-                    user_id,
+                    user_id.clone(),
                     User::new(
                         self.snx_resource_address,
                         self.synthetics_global_debt_share_resource_address,
                     ),
                 );
+                self.user_ids.push(user_id.clone());
                 self.users.get_mut(&user_id).unwrap()
             } else {
                 panic!("User not found");
@@ -277,6 +1062,16 @@ pub struct SyntheticToken {
     asset_address: ResourceAddress,
     /// The synth (sXYZ) resource address
     token_resource_address: ResourceAddress,
+    /// The largest supply which may be minted, if capped
+    mint_cap: Option<Decimal>,
+    /// Whether minting and exchanging are paused
+    paused: bool,
+    /// The price the synth was frozen at when delisted
+    delisted_price: Option<Decimal>,
+    /// The value of the supply, as counted in the global debt
+    debt: Decimal,
+    /// The epoch the supply was last valued at
+    last_revalued_epoch: u64,
 }
 
 impl SyntheticToken {
@@ -289,14 +1084,38 @@ impl SyntheticToken {
             asset_symbol,
             asset_address,
             token_resource_address,
+            mint_cap: None,
+            paused: false,
+            delisted_price: None,
+            debt: Decimal::zero(),
+            last_revalued_epoch: Runtime::current_epoch(),
         }
     }
+
+    // Returns whether this synth may be minted and exchanged
+    pub fn is_tradable(&self) -> bool {
+        !self.paused && self.delisted_price.is_none()
+    }
+
+    // Checks this synth may be minted and exchanged
+    pub fn assert_tradable(&self) {
+        assert!(!self.paused, "s{} is paused", self.asset_symbol);
+        assert!(
+            self.delisted_price.is_none(),
+            "s{} is delisted",
+            self.asset_symbol
+        );
+    }
 }
 
 #[derive(Debug, ScryptoSbor)]
 pub struct User {
     snx: Vault,
     global_debt_share: Vault,
+    /// The reward units per global debt share, when rewards were last accrued
+    reward_per_share_paid: Decimal,
+    /// The reward units accrued and not yet claimed
+    reward_units: Decimal,
 }
 
 impl User {
@@ -304,9 +1123,34 @@ impl User {
         Self {
             snx: Vault::new(snx_address),
             global_debt_share: Vault::new(global_debt_share_address),
+            reward_per_share_paid: Decimal::zero(),
+            reward_units: Decimal::zero(),
         }
     }
 
+    // Accrues the reward units earned by the current global debt share, which must be done before it changes
+    pub fn accrue_rewards(&mut self, reward_per_share: Decimal) {
+        self.reward_units +=
+            self.global_debt_share.amount() * (reward_per_share - self.reward_per_share_paid);
+        self.reward_per_share_paid = reward_per_share;
+    }
+
+    // Returns whether this user has debt and is below the collateralization threshold
+    pub fn is_under_collateralized(
+        &self,
+        snx_price: Decimal,
+        global_debt: Decimal,
+        global_debt_resource_address: ResourceAddress,
+        threshold: Decimal,
+    ) -> bool {
+        let resource_manager = borrow_resource_manager!(global_debt_resource_address);
+        !resource_manager.total_supply().is_zero()
+            && !self.global_debt_share.amount().is_zero()
+            && self.snx.amount() * snx_price
+                / (global_debt / resource_manager.total_supply() * self.global_debt_share.amount())
+                < threshold
+    }
+
     // Checks the collateralization ratio of this user
     pub fn check_collateralization_ratio(
         &self,
@@ -0,0 +1,510 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    public_key: EcdsaSecp256k1PublicKey,
+    account_component: ComponentAddress,
+    price_oracle: ComponentAddress,
+    oracle_admin_badge: ResourceAddress,
+    oracle_owner_badge: ResourceAddress,
+    synthetic_pool: ComponentAddress,
+    admin_badge: ResourceAddress,
+    user_badge: ResourceAddress,
+    exchange_receipt: ResourceAddress,
+    snx: ResourceAddress,
+    usd: ResourceAddress,
+    tsla: ResourceAddress,
+    s_tsla: ResourceAddress,
+}
+
+/// Sets up a SyntheticPool with a collateralization threshold of 400% and an sTSLA synth, priced by a PriceOracle
+/// with a single admin at 10 USD per SNX and 100 USD per TSLA.
+fn setup() -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+    test_runner.set_current_epoch(1);
+
+    // Create an account
+    let (public_key, _private_key, account_component) = test_runner.new_allocated_account();
+
+    // Create the assets to price
+    let snx = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let usd = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+    let tsla = test_runner.create_fungible_resource(dec!("1000000"), 18, account_component);
+
+    // Publish the price oracle next to this package and instantiate it
+    let oracle_package_address =
+        test_runner.compile_and_publish(concat!(env!("CARGO_MANIFEST_DIR"), "/../price-oracle"));
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            oracle_package_address,
+            "PriceOracle",
+            "instantiate_oracle",
+            manifest_args!(1u32, 1u32, 10u64, dec!("0.5")),
+        )
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let price_oracle = receipt.expect_commit(true).new_component_addresses()[0];
+    let oracle_admin_badge = receipt.expect_commit(true).new_resource_addresses()[0];
+    let oracle_owner_badge = receipt.expect_commit(true).new_resource_addresses()[1];
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_pool` function.
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            package_address,
+            "SyntheticPool",
+            "instantiate_pool",
+            manifest_args!(price_oracle, snx, usd, dec!("4")),
+        )
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let synthetic_pool = receipt.expect_commit(true).new_component_addresses()[0];
+    let admin_badge = receipt.expect_commit(true).new_resource_addresses()[0];
+    let user_badge = receipt.expect_commit(true).new_resource_addresses()[3];
+    let exchange_receipt = receipt.expect_commit(true).new_resource_addresses()[4];
+
+    // List sTSLA
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(account_component, admin_badge)
+        .call_method(
+            synthetic_pool,
+            "add_synthetic_token",
+            manifest_args!("TSLA".to_string(), tsla),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let s_tsla = receipt.expect_commit(true).new_resource_addresses()[0];
+
+    let mut env = TestEnv {
+        test_runner,
+        public_key,
+        account_component,
+        price_oracle,
+        oracle_admin_badge,
+        oracle_owner_badge,
+        synthetic_pool,
+        admin_badge,
+        user_badge,
+        exchange_receipt,
+        snx,
+        usd,
+        tsla,
+        s_tsla,
+    };
+
+    // Price the assets
+    update_price(&mut env, snx, dec!("10")).expect_commit_success();
+    update_price(&mut env, tsla, dec!("100")).expect_commit_success();
+
+    env
+}
+
+fn execute(env: &mut TestEnv, manifest: TransactionManifest) -> TransactionReceipt {
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&env.public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Lists a synth of an asset, returning its resource address.
+fn add_synthetic_token(env: &mut TestEnv, symbol: &str, asset: ResourceAddress) -> ResourceAddress {
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, env.admin_badge)
+        .call_method(
+            env.synthetic_pool,
+            "add_synthetic_token",
+            manifest_args!(symbol.to_string(), asset),
+        )
+        .build();
+    let receipt = execute(env, manifest);
+    receipt.expect_commit_success();
+    receipt.expect_commit(true).new_resource_addresses()[0]
+}
+
+/// Reports the price of an asset in USD.
+fn update_price(env: &mut TestEnv, asset: ResourceAddress, price: Decimal) -> TransactionReceipt {
+    let usd = env.usd;
+    report_price(env, asset, usd, price)
+}
+
+/// Reports the price of a resource pair BASE/QUOTE.
+fn report_price(
+    env: &mut TestEnv,
+    base: ResourceAddress,
+    quote: ResourceAddress,
+    price: Decimal,
+) -> TransactionReceipt {
+    let oracle_admin_badge = env.oracle_admin_badge;
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, oracle_admin_badge)
+        .create_proof_from_auth_zone(oracle_admin_badge, |builder, proof| {
+            builder.call_method(
+                env.price_oracle,
+                "update_price",
+                manifest_args!(proof, base, quote, price),
+            )
+        })
+        .build();
+    execute(env, manifest)
+}
+
+/// Registers a user, stakes 1,000 SNX worth 10,000 USD and mints 20 sTSLA worth 2,000 USD against them.
+fn stake_and_mint(env: &mut TestEnv) {
+    let manifest = ManifestBuilder::new()
+        .call_method(env.synthetic_pool, "new_user", manifest_args!())
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest).expect_commit_success();
+
+    let (user_badge, snx) = (env.user_badge, env.snx);
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, snx, dec!("1000"))
+        .create_proof_from_account(env.account_component, user_badge)
+        .take_from_worktop(snx, |builder, bucket| {
+            builder.create_proof_from_auth_zone(user_badge, |builder, proof| {
+                builder.call_method(env.synthetic_pool, "stake", manifest_args!(proof, bucket))
+            })
+        })
+        .create_proof_from_auth_zone(user_badge, |builder, proof| {
+            builder.call_method(
+                env.synthetic_pool,
+                "mint",
+                manifest_args!(proof, dec!("20"), "TSLA".to_string()),
+            )
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest).expect_commit_success();
+}
+
+/// Withdraws staked SNX.
+fn unstake(env: &mut TestEnv, amount: Decimal) -> TransactionReceipt {
+    let user_badge = env.user_badge;
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, user_badge)
+        .create_proof_from_auth_zone(user_badge, |builder, proof| {
+            builder.call_method(env.synthetic_pool, "unstake", manifest_args!(proof, amount))
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+#[test]
+fn test_unstake_within_threshold() {
+    let mut env = setup();
+    stake_and_mint(&mut env);
+
+    // 900 SNX worth 9,000 USD against 2,000 USD of debt is 450%
+    env.test_runner.set_current_epoch(2);
+    unstake(&mut env, dec!("100")).expect_commit_success();
+}
+
+#[test]
+fn test_unstake_is_rejected_after_price_moves() {
+    let mut env = setup();
+    let tsla = env.tsla;
+    stake_and_mint(&mut env);
+
+    // A keeper revalues sTSLA after the move, to a debt of 2,400 USD: 900 SNX would only be 375% of it
+    update_price(&mut env, tsla, dec!("120")).expect_commit_success();
+    let manifest = ManifestBuilder::new()
+        .call_method(
+            env.synthetic_pool,
+            "refresh_debt_cache",
+            manifest_args!(vec!["TSLA".to_string()]),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+    env.test_runner.set_current_epoch(2);
+    unstake(&mut env, dec!("100")).expect_commit_failure();
+
+    // 990 SNX are 412.5% of it
+    unstake(&mut env, dec!("10")).expect_commit_success();
+}
+
+/// Exchanges sTSLA for the synth of TARGET_SYMBOL, keeping the receipt.
+fn exchange(env: &mut TestEnv, amount: Decimal, target_symbol: &str) -> TransactionReceipt {
+    let (user_badge, s_tsla) = (env.user_badge, env.s_tsla);
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, s_tsla, amount)
+        .create_proof_from_account(env.account_component, user_badge)
+        .take_from_worktop(s_tsla, |builder, bucket| {
+            builder.create_proof_from_auth_zone(user_badge, |builder, proof| {
+                builder.call_method(
+                    env.synthetic_pool,
+                    "exchange",
+                    manifest_args!(proof, bucket, target_symbol.to_string()),
+                )
+            })
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+/// Settles the exchange receipt held and asserts at least `min_amount` of OUTPUT is returned.
+fn settle_exchange(
+    env: &mut TestEnv,
+    output: ResourceAddress,
+    min_amount: Decimal,
+) -> TransactionReceipt {
+    let exchange_receipt = env.exchange_receipt;
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(env.account_component, exchange_receipt, dec!("1"))
+        .take_from_worktop(exchange_receipt, |builder, bucket| {
+            builder.call_method(
+                env.synthetic_pool,
+                "settle_exchange",
+                manifest_args!(bucket),
+            )
+        })
+        .assert_worktop_contains_by_amount(min_amount, output)
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+/// Lists sGOLD, priced at 50 USD.
+fn list_gold(env: &mut TestEnv) -> ResourceAddress {
+    let account_component = env.account_component;
+    let gold = env
+        .test_runner
+        .create_fungible_resource(dec!("1000000"), 18, account_component);
+    let s_gold = add_synthetic_token(env, "GOLD", gold);
+    update_price(env, gold, dec!("50")).expect_commit_success();
+    s_gold
+}
+
+#[test]
+fn test_exchange_settles_at_first_prices_after_delay() {
+    let mut env = setup();
+    let tsla = env.tsla;
+    stake_and_mint(&mut env);
+    let s_gold = list_gold(&mut env);
+
+    exchange(&mut env, dec!("10"), "GOLD").expect_commit_success();
+
+    // Nothing settles before the delay
+    env.test_runner.set_current_epoch(2);
+    settle_exchange(&mut env, s_gold, dec!("0")).expect_commit_failure();
+
+    // The first prices of epoch 3 are 110 USD per TSLA, while GOLD is still 50 USD: 10 sTSLA are worth 22 sGOLD,
+    // less the 0.3% fee. The later report doesn't change that.
+    env.test_runner.set_current_epoch(3);
+    update_price(&mut env, tsla, dec!("110")).expect_commit_success();
+    update_price(&mut env, tsla, dec!("120")).expect_commit_success();
+    settle_exchange(&mut env, s_gold, dec!("21.935")).expect_commit_failure();
+    settle_exchange(&mut env, s_gold, dec!("21.934")).expect_commit_success();
+}
+
+#[test]
+fn test_exchange_without_settlement_price_is_cancelled() {
+    let mut env = setup();
+    let s_tsla = env.s_tsla;
+    stake_and_mint(&mut env);
+    let s_gold = list_gold(&mut env);
+
+    exchange(&mut env, dec!("10"), "GOLD").expect_commit_success();
+
+    // No price is reported from epoch 3 on, so it can't settle until the window is over, and then it is cancelled
+    env.test_runner.set_current_epoch(15);
+    settle_exchange(&mut env, s_gold, dec!("0")).expect_commit_failure();
+    env.test_runner.set_current_epoch(16);
+    settle_exchange(&mut env, s_tsla, dec!("10")).expect_commit_success();
+}
+
+#[test]
+fn test_exchange_into_synth_priced_through_hub_is_rejected() {
+    let mut env = setup();
+    let (account_component, oracle_owner_badge, usd) =
+        (env.account_component, env.oracle_owner_badge, env.usd);
+    stake_and_mint(&mut env);
+
+    // GOLD is only priced against a hub asset, at 2 hub units of 25 USD each
+    let gold = env
+        .test_runner
+        .create_fungible_resource(dec!("1000000"), 18, account_component);
+    let hub = env
+        .test_runner
+        .create_fungible_resource(dec!("1000000"), 18, account_component);
+    add_synthetic_token(&mut env, "GOLD", gold);
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(account_component, oracle_owner_badge)
+        .call_method(env.price_oracle, "add_hub_asset", manifest_args!(hub))
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+    report_price(&mut env, gold, hub, dec!("2")).expect_commit_success();
+    report_price(&mut env, hub, usd, dec!("25")).expect_commit_success();
+
+    // Cross rates are never kept as settlement prices
+    exchange(&mut env, dec!("10"), "GOLD").expect_commit_failure();
+
+    update_price(&mut env, gold, dec!("50")).expect_commit_success();
+    exchange(&mut env, dec!("10"), "GOLD").expect_commit_success();
+}
+
+/// Migrates the legacy user identified by LEGACY_BADGE to a new user badge.
+fn migrate_user(env: &mut TestEnv, legacy_badge: ResourceAddress) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(env.account_component, legacy_badge)
+        .create_proof_from_auth_zone(legacy_badge, |builder, proof| {
+            builder.call_method(env.synthetic_pool, "migrate_user", manifest_args!(proof))
+        })
+        .call_method(
+            env.account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest)
+}
+
+#[test]
+fn test_legacy_user_migrates_once() {
+    let mut env = setup();
+    let (account_component, admin_badge, snx, usd) =
+        (env.account_component, env.admin_badge, env.snx, env.usd);
+
+    // A user of the previous version identified with a badge of their own and staked 1,000 SNX
+    let legacy_badge = env
+        .test_runner
+        .create_fungible_resource(dec!("1"), 0, account_component);
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account_component, snx, dec!("1000"))
+        .take_from_worktop(snx, |builder, bucket| {
+            builder.call_method(
+                env.synthetic_pool,
+                "import_legacy_user",
+                manifest_args!(legacy_badge, bucket, dec!("0")),
+            )
+        })
+        .call_method(
+            account_component,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_failure();
+
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(account_component, admin_badge)
+        .withdraw_from_account(account_component, snx, dec!("1000"))
+        .take_from_worktop(snx, |builder, bucket| {
+            builder.call_method(
+                env.synthetic_pool,
+                "import_legacy_user",
+                manifest_args!(legacy_badge, bucket, dec!("0")),
+            )
+        })
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+
+    // Badges nobody imported state for can't be migrated
+    migrate_user(&mut env, usd).expect_commit_failure();
+
+    // The new user badge carries the stake
+    migrate_user(&mut env, legacy_badge).expect_commit_success();
+    unstake(&mut env, dec!("1000")).expect_commit_success();
+
+    migrate_user(&mut env, legacy_badge).expect_commit_failure();
+}
+
+#[test]
+fn test_redeem_delisted_retires_debt_at_final_price() {
+    let mut env = setup();
+    let (account_component, admin_badge, user_badge, tsla, s_tsla) = (
+        env.account_component,
+        env.admin_badge,
+        env.user_badge,
+        env.tsla,
+        env.s_tsla,
+    );
+    stake_and_mint(&mut env);
+
+    // sTSLA is frozen at 100 USD, whatever TSLA does next
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(account_component, admin_badge)
+        .call_method(
+            env.synthetic_pool,
+            "delist_synthetic_token",
+            manifest_args!("TSLA".to_string()),
+        )
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+    update_price(&mut env, tsla, dec!("120")).expect_commit_success();
+
+    // Redeeming 10 sTSLA retires 1,000 USD of the 2,000 USD of debt
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account_component, s_tsla, dec!("10"))
+        .create_proof_from_account(account_component, user_badge)
+        .take_from_worktop(s_tsla, |builder, bucket| {
+            builder.create_proof_from_auth_zone(user_badge, |builder, proof| {
+                builder.call_method(
+                    env.synthetic_pool,
+                    "redeem_delisted",
+                    manifest_args!(proof, bucket),
+                )
+            })
+        })
+        .build();
+    execute(&mut env, manifest).expect_commit_success();
+
+    // 400 SNX are 400% of what is left
+    env.test_runner.set_current_epoch(2);
+    unstake(&mut env, dec!("601")).expect_commit_failure();
+    unstake(&mut env, dec!("600")).expect_commit_success();
+}
@@ -1,16 +1,68 @@
 use scrypto::prelude::*;
 
+external_component! {
+    PriceOracleComponentTarget {
+        fn get_price(&self, base: ResourceAddress, quote: ResourceAddress) -> Option<Decimal>;
+    }
+}
+
+/// The number of epochs in a day, at about 5 minutes each. Funding closes the gap between mark and index price
+/// over about a day.
+const EPOCHS_PER_DAY: u64 = 288;
+
+#[derive(NonFungibleData, ScryptoSbor)]
+pub struct Trader {
+    /// The epoch the trader registered at
+    pub registered_epoch: u64,
+}
+
 #[blueprint]
 mod clearing_house {
     struct ClearingHouse {
         /// All traders' positions
-        trader_positions: KeyValueStore<ResourceAddress, Vec<Position>>,
+        trader_positions: KeyValueStore<NonFungibleLocalId, Vec<Position>>,
+        /// The positions of traders of the previous version, keyed by the resource of the badge they identified
+        /// with, until they migrate to a user badge
+        legacy_positions: KeyValueStore<ResourceAddress, Vec<Position>>,
         /// Deposit vault
         deposits_in_quote: Vault,
         /// Liquidation threshold
         liquidation_threshold: Decimal,
+        /// Insurance fund, covering the losses of positions beyond their margin
+        insurance_fund: Vault,
+        /// The fee charged on opening a position, as a share of its size
+        trading_fee: Decimal,
+        /// The share of trading fees and liquidation penalties which goes to the insurance fund
+        insurance_fund_share: Decimal,
+        /// Losses beyond margin and payouts beyond the deposits which the insurance fund couldn't cover, recovered
+        /// from the profits of traders
+        bad_debt: Decimal,
         /// Virtual AMM
         amm: AMM,
+        /// The price oracle
+        oracle_address: ComponentAddress,
+        /// The resource address of the base asset, for looking up the index price
+        base_address: ResourceAddress,
+        /// The number of epochs between funding payments
+        funding_period: u64,
+        /// The epoch funding was last settled at
+        last_funding_epoch: u64,
+        /// The funding paid per unit of base held long since instantiation, in quote
+        cumulative_funding_index: Decimal,
+        /// The mark price summed over every epoch since instantiation
+        cumulative_mark_price: Decimal,
+        /// The index price summed over every epoch since instantiation
+        cumulative_index_price: Decimal,
+        /// The cumulative mark and index prices when funding was last settled
+        funding_cumulative_prices: (Decimal, Decimal),
+        /// The epoch prices were last observed at
+        last_observation_epoch: u64,
+        /// The mark and index prices observed last, which hold until the next observation
+        last_observed_prices: (Decimal, Decimal),
+        /// User badge mint badge
+        user_badge_mint_badge: Vault,
+        /// The user badge resource address
+        user_badge_resource_address: ResourceAddress,
     }
 
     impl ClearingHouse {
@@ -18,45 +70,109 @@ mod clearing_house {
             quote_address: ResourceAddress,
             base_init_supply: Decimal,
             quote_init_supply: Decimal,
-        ) -> ComponentAddress {
-            Self {
+            oracle_address: ComponentAddress,
+            base_address: ResourceAddress,
+            funding_period: u64,
+        ) -> (ComponentAddress, Bucket) {
+            assert!(funding_period > 0, "Funding period must be positive");
+
+            // Until the oracle has a price, the index is taken to be the mark, so that no funding accrues
+            let mark_price = quote_init_supply / base_init_supply;
+            let oracle: PriceOracleComponentTarget = oracle_address.into();
+            let index_price = oracle
+                .get_price(base_address, quote_address)
+                .unwrap_or(mark_price);
+
+            let user_badge_mint_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "xPerpFutures User Badge Mint Auth")
+                .mint_initial_supply(1);
+            let user_badge_resource_address = ResourceBuilder::new_uuid_non_fungible::<Trader>()
+                .metadata("name", "xPerpFutures User Badge")
+                .mintable(
+                    rule!(require(user_badge_mint_badge.resource_address())),
+                    LOCKED,
+                )
+                .create_with_no_initial_supply();
+            let admin_badge = ResourceBuilder::new_fungible()
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata("name", "xPerpFutures Admin Badge")
+                .mint_initial_supply(1);
+
+            let rules = AccessRulesConfig::new()
+                .method(
+                    "import_legacy_positions",
+                    rule!(require(admin_badge.resource_address())),
+                    AccessRule::DenyAll,
+                )
+                .default(rule!(allow_all), AccessRule::DenyAll);
+
+            let component = Self {
                 trader_positions: KeyValueStore::new(),
+                legacy_positions: KeyValueStore::new(),
                 deposits_in_quote: Vault::new(quote_address),
                 liquidation_threshold: "0.06".parse().unwrap(),
+                insurance_fund: Vault::new(quote_address),
+                trading_fee: "0.001".parse().unwrap(),
+                insurance_fund_share: "0.5".parse().unwrap(),
+                bad_debt: Decimal::zero(),
                 amm: AMM {
                     base_supply: base_init_supply,
                     quote_supply: quote_init_supply,
                 },
+                oracle_address,
+                base_address,
+                funding_period,
+                last_funding_epoch: Runtime::current_epoch(),
+                cumulative_funding_index: Decimal::zero(),
+                cumulative_mark_price: Decimal::zero(),
+                cumulative_index_price: Decimal::zero(),
+                funding_cumulative_prices: (Decimal::zero(), Decimal::zero()),
+                last_observation_epoch: Runtime::current_epoch(),
+                last_observed_prices: (mark_price, index_price),
+                user_badge_mint_badge: Vault::with_bucket(user_badge_mint_badge),
+                user_badge_resource_address,
             }
-            .instantiate()
-            .globalize()
+            .instantiate();
+            let component_address = component.globalize_with_access_rules(rules);
+
+            (component_address, admin_badge)
         }
 
         /// Creates a position.
         pub fn new_position(
             &mut self,
             user_auth: Proof,
-            margin: Bucket,
+            mut margin: Bucket,
             leverage: Decimal,
             position_type: String, // TODO: make CLI support enum
         ) {
             assert!(leverage >= dec!("1") && leverage <= dec!("16"));
-            let user_id = Self::get_user_id(user_auth);
+            let user_id = self.get_user_id(user_auth);
             let position_type = match position_type.as_str() {
                 "Long" => PositionType::Long,
                 "Short" => PositionType::Short,
                 _ => panic!("Invalid position type"),
             };
 
+            let mut fee = margin.take(margin.amount() * leverage * self.trading_fee);
+            self.insurance_fund
+                .put(fee.take(fee.amount() * self.insurance_fund_share));
+            self.deposits_in_quote.put(fee);
+
             let margin_amount = margin.amount();
-            let position = self
-                .amm
-                .new_position(margin_amount, leverage, position_type);
+            let position = self.amm.new_position(
+                margin_amount,
+                leverage,
+                position_type,
+                self.cumulative_funding_index,
+            );
+            self.observe_prices();
 
             let mut positions = match self.trader_positions.get_mut(&user_id) {
                 Some(positions) => positions,
                 None => {
-                    self.trader_positions.insert(user_id, Vec::new());
+                    self.trader_positions.insert(user_id.clone(), Vec::new());
                     self.trader_positions.get_mut(&user_id).unwrap()
                 }
             };
@@ -68,18 +184,45 @@ mod clearing_house {
 
         /// Settles a position.
         pub fn settle_position(&mut self, user_auth: Proof, nth: usize) -> Bucket {
-            let user_id = Self::get_user_id(user_auth);
+            let user_id = self.get_user_id(user_auth);
             self.settle_internal(user_id, nth)
         }
 
-        /// Liquidate a position.
-        pub fn liquidate(&mut self, user_id: ResourceAddress, nth: usize) -> Bucket {
+        /// Liquidate a position. What is left of its margin is the liquidation penalty, shared between the
+        /// insurance fund and the liquidator.
+        pub fn liquidate(&mut self, user_id: NonFungibleLocalId, nth: usize) -> Bucket {
             assert!(
-                self.get_margin_ratio(user_id, nth) <= self.liquidation_threshold,
+                self.get_margin_ratio(user_id.clone(), nth) <= self.liquidation_threshold,
                 "Position can't be liquidated"
             );
 
-            self.settle_internal(user_id, nth)
+            let mut penalty = self.settle_internal(user_id, nth);
+            self.insurance_fund
+                .put(penalty.take(penalty.amount() * self.insurance_fund_share));
+            penalty
+        }
+
+        /// Settles funding for the epochs since it was last settled, once a funding period has passed. Longs pay shorts
+        /// while the mark price is above the index price, and shorts pay longs while it is below, both time-weighted
+        /// over those epochs.  Funding accrues for every epoch elapsed, however late it is settled.
+        pub fn settle_funding(&mut self) {
+            let current_epoch = Runtime::current_epoch();
+            assert!(
+                current_epoch >= self.last_funding_epoch + self.funding_period,
+                "Funding is due at epoch {}",
+                self.last_funding_epoch + self.funding_period
+            );
+
+            self.observe_prices();
+            let elapsed = current_epoch - self.last_funding_epoch;
+            let (funding_mark_price, funding_index_price) = self.funding_cumulative_prices;
+            let mark_twap = (self.cumulative_mark_price - funding_mark_price) / elapsed;
+            let index_twap = (self.cumulative_index_price - funding_index_price) / elapsed;
+            let funding_per_base = (mark_twap - index_twap) * elapsed / EPOCHS_PER_DAY;
+            self.cumulative_funding_index += funding_per_base;
+            self.funding_cumulative_prices =
+                (self.cumulative_mark_price, self.cumulative_index_price);
+            self.last_funding_epoch = current_epoch;
         }
 
         /// Returns the running price.
@@ -87,16 +230,36 @@ mod clearing_house {
             self.amm.get_price()
         }
 
+        /// Returns the index price of the base asset, from the oracle.
+        pub fn get_index_price(&self) -> Decimal {
+            let oracle: PriceOracleComponentTarget = self.oracle_address.into();
+            oracle
+                .get_price(self.base_address, self.deposits_in_quote.resource_address())
+                .expect("Index price unavailable")
+        }
+
+        /// Returns the cumulative funding index and the epoch funding was last settled at.
+        pub fn get_funding_index(&self) -> (Decimal, u64) {
+            (self.cumulative_funding_index, self.last_funding_epoch)
+        }
+
+        /// Returns the funding a position owes, negative if it is owed funding.
+        pub fn get_funding_payment(&self, user_id: NonFungibleLocalId, nth: usize) -> Decimal {
+            let position = self.get_position(user_id, nth);
+            position.funding_payment(self.cumulative_funding_index)
+        }
+
         /// Returns the n-th position of a user
-        pub fn get_position(&self, user_id: ResourceAddress, nth: usize) -> Position {
+        pub fn get_position(&self, user_id: NonFungibleLocalId, nth: usize) -> Position {
             let positions = self.trader_positions.get(&user_id).unwrap();
             positions.get(nth).unwrap().clone()
         }
 
         /// Returns the margin ratio of a specific position
-        pub fn get_margin_ratio(&self, user_id: ResourceAddress, nth: usize) -> Decimal {
+        pub fn get_margin_ratio(&self, user_id: NonFungibleLocalId, nth: usize) -> Decimal {
             let position = self.get_position(user_id, nth);
-            self.amm.get_margin_ratio(&position)
+            self.amm
+                .get_margin_ratio(&position, self.cumulative_funding_index)
         }
 
         /// Donates into this protocol.
@@ -104,35 +267,188 @@ mod clearing_house {
             self.deposits_in_quote.put(donation);
         }
 
-        /// Registers a new user
+        /// Donates into the insurance fund.
+        pub fn donate_to_insurance_fund(&mut self, donation: Bucket) {
+            self.insurance_fund.put(donation);
+        }
+
+        /// Returns the amount in the insurance fund.
+        pub fn get_insurance_fund(&self) -> Decimal {
+            self.insurance_fund.amount()
+        }
+
+        /// Returns the bad debt not yet recovered.
+        pub fn get_bad_debt(&self) -> Decimal {
+            self.bad_debt
+        }
+
+        /// Registers a new user, returning the badge which identifies them
         pub fn new_user(&self) -> Bucket {
-            ResourceBuilder::new_fungible()
-                .divisibility(DIVISIBILITY_NONE)
-                .metadata("name", "xPerpFutures User Badge")
-                .mint_initial_supply(1)
+            self.user_badge_mint_badge.authorize(|| {
+                borrow_resource_manager!(self.user_badge_resource_address).mint_uuid_non_fungible(
+                    Trader {
+                        registered_epoch: Runtime::current_epoch(),
+                    },
+                )
+            })
+        }
+
+        /// Records the positions of a trader of the previous version, who identified with any badge of LEGACY_BADGE,
+        /// along with the margin backing them, so that they can take them over through `migrate_user`.  The virtual
+        /// AMM must have been instantiated with the supplies these positions left it at.
+        pub fn import_legacy_positions(
+            &mut self,
+            legacy_badge: ResourceAddress,
+            positions: Vec<Position>,
+            margin: Bucket,
+        ) {
+            assert!(
+                self.legacy_positions.get(&legacy_badge).is_none(),
+                "Legacy positions already imported"
+            );
+            assert!(!positions.is_empty(), "No positions to import");
+            let total_margin = positions.iter().fold(Decimal::zero(), |total, position| {
+                total + position.margin_in_quote
+            });
+            assert!(
+                margin.amount() == total_margin,
+                "Margin doesn't match the positions"
+            );
+
+            self.deposits_in_quote.put(margin);
+            self.legacy_positions.insert(legacy_badge, positions);
+        }
+
+        /// Migrates a trader of the previous version, identified by a badge they present, to a new user badge
+        /// carrying their positions. Each legacy trader may migrate once.
+        pub fn migrate_user(&mut self, legacy_auth: Proof) -> Bucket {
+            let legacy_badge = legacy_auth.resource_address();
+            legacy_auth
+                .validate_proof(ProofValidationMode::ValidateContainsAmount(
+                    legacy_badge,
+                    dec!("1"),
+                ))
+                .expect("Invalid legacy badge");
+            let positions = self
+                .legacy_positions
+                .get(&legacy_badge)
+                .expect("No legacy positions for this badge")
+                .to_vec();
+            assert!(!positions.is_empty(), "Legacy positions already migrated");
+            self.legacy_positions.insert(legacy_badge, Vec::new());
+
+            let user_badge = self.new_user();
+            self.trader_positions
+                .insert(user_badge.non_fungible_local_id(), positions);
+            user_badge
+        }
+
+        /// Returns the user badge resource address.
+        pub fn user_badge_address(&self) -> ResourceAddress {
+            self.user_badge_resource_address
         }
 
         /// Parse user id from proof.
-        fn get_user_id(user_auth: Proof) -> ResourceAddress {
-            user_auth.unsafe_skip_proof_validation().resource_address()
+        fn get_user_id(&self, user_auth: Proof) -> NonFungibleLocalId {
+            user_auth
+                .validate_proof(ProofValidationMode::ValidateContainsAmount(
+                    self.user_badge_resource_address,
+                    dec!("1"),
+                ))
+                .expect("Invalid user badge")
+                .non_fungible_local_id()
         }
 
-        fn settle_internal(&mut self, user_id: ResourceAddress, nth: usize) -> Bucket {
+        fn settle_internal(&mut self, user_id: NonFungibleLocalId, nth: usize) -> Bucket {
             let mut positions = self.trader_positions.get_mut(&user_id).unwrap();
             let position = positions.get(nth).unwrap();
 
             let pnl = self.amm.settle_position(position);
+            let funding = position.funding_payment(self.cumulative_funding_index);
             debug!(
-                "Margin: {}, PnL: {}, Vault balance: {}",
+                "Margin: {}, PnL: {}, Funding: {}, Vault balance: {}",
                 position.margin_in_quote,
                 pnl,
+                funding,
                 self.deposits_in_quote.amount()
             );
-            let to_return = position.margin_in_quote + pnl;
+            let margin = position.margin_in_quote;
+            let equity = margin + pnl - funding;
 
             positions.swap_remove(nth);
             self.trader_positions.insert(user_id, positions.to_vec());
-            self.deposits_in_quote.take(to_return)
+            self.observe_prices();
+
+            if equity.is_negative() {
+                self.cover_loss(-equity);
+                Bucket::new(self.deposits_in_quote.resource_address())
+            } else {
+                self.pay_out(margin, equity)
+            }
+        }
+
+        /// Adds the prices observed last to the cumulative prices for every epoch since, then observes the current
+        /// ones.  Called whenever the mark price changes, so that the prices observed last hold until the next
+        /// observation.  The index price is left as is while the oracle has none.
+        fn observe_prices(&mut self) {
+            let current_epoch = Runtime::current_epoch();
+            let elapsed = current_epoch - self.last_observation_epoch;
+            let (mark_price, index_price) = self.last_observed_prices;
+            self.cumulative_mark_price += mark_price * elapsed;
+            self.cumulative_index_price += index_price * elapsed;
+            self.last_observation_epoch = current_epoch;
+
+            let oracle: PriceOracleComponentTarget = self.oracle_address.into();
+            let index_price = oracle
+                .get_price(self.base_address, self.deposits_in_quote.resource_address())
+                .unwrap_or(index_price);
+            self.last_observed_prices = (self.amm.get_price(), index_price);
+        }
+
+        /// Records the loss of a position beyond its margin as bad debt, paying down what the insurance fund can.
+        fn cover_loss(&mut self, loss: Decimal) {
+            self.bad_debt += loss;
+            self.pay_down_bad_debt();
+        }
+
+        /// Pays down as much bad debt as the insurance fund can, into the deposits.
+        fn pay_down_bad_debt(&mut self) {
+            let covered = if self.bad_debt > self.insurance_fund.amount() {
+                self.insurance_fund.amount()
+            } else {
+                self.bad_debt
+            };
+            self.deposits_in_quote
+                .put(self.insurance_fund.take(covered));
+            self.bad_debt -= covered;
+        }
+
+        /// Pays out the equity of a position.  Bad debt the insurance fund can't pay down is borne pro rata by the
+        /// profits paid out while it lasts: each profit is cut by the share of the bad debt in what the deposits
+        /// should hold, `bad_debt / (deposits + bad_debt)`, and the cut pays the bad debt down.  What the deposits
+        /// can't pay even then is recorded as bad debt in turn.
+        fn pay_out(&mut self, margin: Decimal, equity: Decimal) -> Bucket {
+            self.pay_down_bad_debt();
+            let mut payout = equity;
+            let profit = equity - margin;
+            if profit.is_positive() && self.bad_debt.is_positive() {
+                let mut haircut =
+                    profit * self.bad_debt / (self.deposits_in_quote.amount() + self.bad_debt);
+                if haircut > self.bad_debt {
+                    haircut = self.bad_debt;
+                }
+                payout -= haircut;
+                self.bad_debt -= haircut;
+            }
+
+            if payout > self.deposits_in_quote.amount() {
+                self.bad_debt += payout - self.deposits_in_quote.amount();
+                self.pay_down_bad_debt();
+                if payout > self.deposits_in_quote.amount() {
+                    payout = self.deposits_in_quote.amount();
+                }
+            }
+            self.deposits_in_quote.take(payout)
         }
     }
 }
@@ -153,6 +469,15 @@ pub struct Position {
     pub leverage: Decimal,
     /// The position in base, positive for long and negative for short
     pub position_in_base: Decimal,
+    /// The cumulative funding index when the position was opened
+    pub entry_funding_index: Decimal,
+}
+
+impl Position {
+    /// Returns the funding owed since the position was opened, negative if it is owed funding
+    pub fn funding_payment(&self, cumulative_funding_index: Decimal) -> Decimal {
+        self.position_in_base * (cumulative_funding_index - self.entry_funding_index)
+    }
 }
 
 #[derive(ScryptoSbor)]
@@ -170,6 +495,7 @@ impl AMM {
         margin_in_quote: Decimal,
         leverage: Decimal,
         position_type: PositionType,
+        funding_index: Decimal,
     ) -> Position {
         // Calculate the new quote & base supply
         let k = self.base_supply * self.quote_supply;
@@ -190,6 +516,7 @@ impl AMM {
             margin_in_quote,
             leverage,
             position_in_base,
+            entry_funding_index: funding_index,
         }
     }
 
@@ -209,9 +536,10 @@ impl AMM {
         self.quote_supply / self.base_supply
     }
 
-    /// Returns the margin ratio of a position
-    pub fn get_margin_ratio(&self, position: &Position) -> Decimal {
-        (position.margin_in_quote + self.get_pnl(position))
+    /// Returns the margin ratio of a position, net of the funding it owes
+    pub fn get_margin_ratio(&self, position: &Position, funding_index: Decimal) -> Decimal {
+        (position.margin_in_quote + self.get_pnl(position)
+            - position.funding_payment(funding_index))
             / (self.get_price() * position.position_in_base.abs())
     }
 
@@ -0,0 +1,373 @@
+use radix_engine::transaction::TransactionReceipt;
+use scrypto::prelude::*;
+use scrypto_unit::*;
+use transaction::builder::ManifestBuilder;
+
+struct TestEnv {
+    test_runner: TestRunner,
+    clearing_house: ComponentAddress,
+    price_oracle: ComponentAddress,
+    oracle_admin_badge: ResourceAddress,
+    admin_badge: ResourceAddress,
+    user_badge: ResourceAddress,
+    quote: ResourceAddress,
+    base: ResourceAddress,
+    long_trader: (EcdsaSecp256k1PublicKey, ComponentAddress),
+    short_trader: (EcdsaSecp256k1PublicKey, ComponentAddress),
+}
+
+/// Sets up a ClearingHouse with a virtual AMM of 1,000 base and 100,000 quote, and two registered traders with
+/// 10,000 quote each.
+fn setup() -> TestEnv {
+    // Setup the environment
+    let mut test_runner = TestRunner::builder().build();
+    test_runner.set_current_epoch(1);
+
+    // Create the accounts of the traders
+    let (long_public_key, _private_key, long_account) = test_runner.new_allocated_account();
+    let (short_public_key, _private_key, short_account) = test_runner.new_allocated_account();
+
+    // Create the quote and base resources, and give the long trader some quote too
+    let quote = test_runner.create_fungible_resource(dec!("20000"), 18, short_account);
+    let base = test_runner.create_fungible_resource(dec!("1000"), 18, short_account);
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(short_account, quote, dec!("10000"))
+        .call_method(
+            long_account,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&short_public_key)],
+    );
+    receipt.expect_commit_success();
+
+    // Publish the price oracle next to this package and instantiate it, without any price
+    let oracle_package_address =
+        test_runner.compile_and_publish(concat!(env!("CARGO_MANIFEST_DIR"), "/../price-oracle"));
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            oracle_package_address,
+            "PriceOracle",
+            "instantiate_oracle",
+            manifest_args!(1u32, 1u32, 10u64, dec!("0.1")),
+        )
+        .call_method(
+            short_account,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&short_public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let price_oracle = receipt.expect_commit(true).new_component_addresses()[0];
+    let oracle_admin_badge = receipt.expect_commit(true).new_resource_addresses()[0];
+
+    // Publish package
+    let package_address = test_runner.compile_and_publish(this_package!());
+
+    // Test the `instantiate_clearing_house` function.
+    let manifest = ManifestBuilder::new()
+        .call_function(
+            package_address,
+            "ClearingHouse",
+            "instantiate_clearing_house",
+            manifest_args!(
+                quote,
+                dec!("1000"),
+                dec!("100000"),
+                price_oracle,
+                base,
+                12u64
+            ),
+        )
+        .call_method(
+            short_account,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    let receipt = test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&short_public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt.expect_commit_success();
+    let clearing_house = receipt.expect_commit(true).new_component_addresses()[0];
+    let user_badge = receipt.expect_commit(true).new_resource_addresses()[1];
+    let admin_badge = receipt.expect_commit(true).new_resource_addresses()[2];
+
+    let mut env = TestEnv {
+        test_runner,
+        clearing_house,
+        price_oracle,
+        oracle_admin_badge,
+        admin_badge,
+        user_badge,
+        quote,
+        base,
+        long_trader: (long_public_key, long_account),
+        short_trader: (short_public_key, short_account),
+    };
+
+    // Register both traders
+    for trader in [env.long_trader, env.short_trader] {
+        let manifest = ManifestBuilder::new()
+            .call_method(clearing_house, "new_user", manifest_args!())
+            .call_method(
+                trader.1,
+                "deposit_batch",
+                manifest_args!(ManifestExpression::EntireWorktop),
+            )
+            .build();
+        execute(&mut env, manifest, trader.0).expect_commit_success();
+    }
+
+    env
+}
+
+fn execute(
+    env: &mut TestEnv,
+    manifest: TransactionManifest,
+    public_key: EcdsaSecp256k1PublicKey,
+) -> TransactionReceipt {
+    let receipt = env.test_runner.execute_manifest_ignoring_fee(
+        manifest,
+        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+    );
+    println!("{:?}\n", receipt);
+    receipt
+}
+
+/// Opens a position of a trader with `margin` quote.
+fn new_position(
+    env: &mut TestEnv,
+    (public_key, account): (EcdsaSecp256k1PublicKey, ComponentAddress),
+    margin: Decimal,
+    leverage: Decimal,
+    position_type: &str,
+) -> TransactionReceipt {
+    let (user_badge, quote) = (env.user_badge, env.quote);
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(account, quote, margin)
+        .create_proof_from_account(account, user_badge)
+        .take_from_worktop(quote, |builder, bucket| {
+            builder.create_proof_from_auth_zone(user_badge, |builder, proof| {
+                builder.call_method(
+                    env.clearing_house,
+                    "new_position",
+                    manifest_args!(proof, bucket, leverage, position_type.to_string()),
+                )
+            })
+        })
+        .build();
+    execute(env, manifest, public_key)
+}
+
+/// Settles the first position of a trader and asserts at least `min_payout` quote is paid out.
+fn settle_position(
+    env: &mut TestEnv,
+    (public_key, account): (EcdsaSecp256k1PublicKey, ComponentAddress),
+    min_payout: Decimal,
+) -> TransactionReceipt {
+    let (user_badge, quote) = (env.user_badge, env.quote);
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(account, user_badge)
+        .create_proof_from_auth_zone(user_badge, |builder, proof| {
+            builder.call_method(
+                env.clearing_house,
+                "settle_position",
+                manifest_args!(proof, 0usize),
+            )
+        })
+        .assert_worktop_contains_by_amount(min_payout, quote)
+        .call_method(
+            account,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest, public_key)
+}
+
+/// Opens a 10x long of 100 quote and a 10x short of 1,000 quote, which moves the price from 100 to ~82.97, then
+/// settles the long at a loss of ~84.81 beyond its margin, only 5.5 of which the insurance fund covers. The other
+/// ~79.31 are bad debt. The short trader donates 1,000 quote to the deposits.
+fn setup_bad_debt() -> TestEnv {
+    let mut env = setup();
+    let (long_trader, short_trader, quote) = (env.long_trader, env.short_trader, env.quote);
+
+    new_position(&mut env, long_trader, dec!("100"), dec!("10"), "Long").expect_commit_success();
+    new_position(&mut env, short_trader, dec!("1000"), dec!("10"), "Short").expect_commit_success();
+    settle_position(&mut env, long_trader, dec!("0")).expect_commit_success();
+
+    let manifest = ManifestBuilder::new()
+        .withdraw_from_account(short_trader.1, quote, dec!("1000"))
+        .take_from_worktop(quote, |builder, bucket| {
+            builder.call_method(env.clearing_house, "donate", manifest_args!(bucket))
+        })
+        .build();
+    execute(&mut env, manifest, short_trader.0).expect_commit_success();
+
+    env
+}
+
+#[test]
+fn test_profit_bears_bad_debt_beyond_insurance_fund() {
+    // The short's equity is ~1,173.81, a profit of ~183.81 on its 990 margin. It is cut by the share of the bad debt
+    // in what the deposits should hold, ~79.31 / (2,100 + ~79.31), so ~6.69 of it pays the bad debt down
+    let mut env = setup_bad_debt();
+    let short_trader = env.short_trader;
+    settle_position(&mut env, short_trader, dec!("1167.12")).expect_commit_success();
+
+    let mut env = setup_bad_debt();
+    let short_trader = env.short_trader;
+    settle_position(&mut env, short_trader, dec!("1167.13")).expect_commit_failure();
+}
+
+#[derive(ManifestSbor)]
+enum ManifestPositionType {
+    Long,
+}
+
+/// Mirrors `Position`, to pass positions in manifests.
+#[derive(ManifestSbor)]
+struct ManifestPosition {
+    position_type: ManifestPositionType,
+    margin_in_quote: Decimal,
+    leverage: Decimal,
+    position_in_base: Decimal,
+    entry_funding_index: Decimal,
+}
+
+/// Imports a 1x long of 100 quote for the trader identified by LEGACY_BADGE, as opened when the virtual AMM stood
+/// at 99,900 quote, which left it at the 100,000 quote of the setup.
+fn import_legacy_long(
+    env: &mut TestEnv,
+    legacy_badge: ResourceAddress,
+    as_admin: bool,
+) -> TransactionReceipt {
+    let (admin_badge, quote, short_trader) = (env.admin_badge, env.quote, env.short_trader);
+    let position = ManifestPosition {
+        position_type: ManifestPositionType::Long,
+        margin_in_quote: dec!("100"),
+        leverage: dec!("1"),
+        position_in_base: dec!("1.001001001001001001"),
+        entry_funding_index: dec!("0"),
+    };
+    let mut builder = ManifestBuilder::new();
+    if as_admin {
+        builder.create_proof_from_account(short_trader.1, admin_badge);
+    }
+    let manifest = builder
+        .withdraw_from_account(short_trader.1, quote, dec!("100"))
+        .take_from_worktop(quote, |builder, bucket| {
+            builder.call_method(
+                env.clearing_house,
+                "import_legacy_positions",
+                manifest_args!(legacy_badge, vec![position], bucket),
+            )
+        })
+        .call_method(
+            short_trader.1,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest, short_trader.0)
+}
+
+/// Migrates the legacy trader identified by LEGACY_BADGE to a new user badge.
+fn migrate_user(
+    env: &mut TestEnv,
+    (public_key, account): (EcdsaSecp256k1PublicKey, ComponentAddress),
+    legacy_badge: ResourceAddress,
+) -> TransactionReceipt {
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(account, legacy_badge)
+        .create_proof_from_auth_zone(legacy_badge, |builder, proof| {
+            builder.call_method(env.clearing_house, "migrate_user", manifest_args!(proof))
+        })
+        .call_method(
+            account,
+            "deposit_batch",
+            manifest_args!(ManifestExpression::EntireWorktop),
+        )
+        .build();
+    execute(env, manifest, public_key)
+}
+
+#[test]
+fn test_legacy_trader_migrates_once() {
+    let mut env = setup();
+    let (public_key, _private_key, account) = env.test_runner.new_allocated_account();
+    let legacy_trader = (public_key, account);
+    let legacy_badge = env
+        .test_runner
+        .create_fungible_resource(dec!("1"), 0, account);
+
+    import_legacy_long(&mut env, legacy_badge, false).expect_commit_failure();
+    import_legacy_long(&mut env, legacy_badge, true).expect_commit_success();
+
+    // The new user badge carries the long, which settles at no profit or loss at the price it left the AMM at
+    migrate_user(&mut env, legacy_trader, legacy_badge).expect_commit_success();
+    settle_position(&mut env, legacy_trader, dec!("100")).expect_commit_success();
+
+    migrate_user(&mut env, legacy_trader, legacy_badge).expect_commit_failure();
+}
+
+/// Returns the cumulative funding index.
+fn get_funding_index(env: &mut TestEnv) -> Decimal {
+    let manifest = ManifestBuilder::new()
+        .call_method(env.clearing_house, "get_funding_index", manifest_args!())
+        .build();
+    let receipt = env
+        .test_runner
+        .execute_manifest_ignoring_fee(manifest, vec![]);
+    println!("{:?}\n", receipt);
+    let (funding_index, _last_funding_epoch): (Decimal, u64) =
+        receipt.expect_commit(true).output(0);
+    funding_index
+}
+
+#[test]
+fn test_funding_accrues_for_every_elapsed_period() {
+    let mut env = setup();
+    let (oracle_admin_badge, base, quote, long_trader, short_trader) = (
+        env.oracle_admin_badge,
+        env.base,
+        env.quote,
+        env.long_trader,
+        env.short_trader,
+    );
+
+    // The index price is 90, and a 1x long of 100 quote, 99.9 after the fee, moves the mark price to ~100.2
+    let manifest = ManifestBuilder::new()
+        .create_proof_from_account(short_trader.1, oracle_admin_badge)
+        .create_proof_from_auth_zone(oracle_admin_badge, |builder, proof| {
+            builder.call_method(
+                env.price_oracle,
+                "update_price",
+                manifest_args!(proof, base, quote, dec!("90")),
+            )
+        })
+        .build();
+    execute(&mut env, manifest, short_trader.0).expect_commit_success();
+    new_position(&mut env, long_trader, dec!("100"), dec!("1"), "Long").expect_commit_success();
+
+    // Settled three funding periods late, longs pay ~10.2 * 36 / 288 = ~1.275 per unit of base, not just the ~0.425
+    // of a single period
+    env.test_runner.set_current_epoch(37);
+    let manifest = ManifestBuilder::new()
+        .call_method(env.clearing_house, "settle_funding", manifest_args!())
+        .build();
+    execute(&mut env, manifest, short_trader.0).expect_commit_success();
+    let funding_index = get_funding_index(&mut env);
+    assert!(funding_index > dec!("1.2749") && funding_index < dec!("1.275"));
+}